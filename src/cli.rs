@@ -1,11 +1,149 @@
-use clap::Parser;
+use crate::constants::DEFAULT_EXPECTED_POW;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{ops::RangeInclusive, path::PathBuf, time::Duration};
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     /// The pattern to look for
     pub peer: Option<String>,
     /// The path to the file to read
-    pub identity_path: Option<std::path::PathBuf>,
-    /// The chain Name
+    #[arg(conflicts_with = "data_dir")]
+    pub identity_path: Option<PathBuf>,
+    /// octez data directory to read identity.json, config.json and peers.json from
+    #[arg(long)]
+    pub data_dir: Option<PathBuf>,
+    /// Chain name of a private network started from the sandbox genesis
+    #[arg(conflicts_with = "network")]
     pub chain_name: Option<String>,
+    /// Network to join, it sets the chain name and bootstrap peers together,
+    /// defaults to the data directory network or mainnet
+    #[arg(long, value_enum)]
+    pub network: Option<Network>,
+    /// Octez network description (JSON) of a custom network, instead of `--network`
+    #[arg(long, conflicts_with_all = ["network", "chain_name"])]
+    pub network_file: Option<PathBuf>,
+    /// Accept handshakes from other nodes on this address instead of dialing a peer,
+    /// without address the data directory listen-addr or `[::]:9732` is used
+    #[arg(long)]
+    pub listen: Option<Option<String>>,
+    /// Simultaneous incoming handshakes before new peers are refused
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+    /// Minimal proof-of-work difficulty required from remote peers
+    #[arg(long)]
+    pub expected_pow: Option<f64>,
+    /// Ask the remote not to send us mempool operations
+    #[arg(long)]
+    pub disable_mempool: bool,
+    /// Ask the remote not to advertise us to other peers
+    #[arg(long)]
+    pub private_node: bool,
+    /// Supported distributed_db versions, a single version or a range like `0-2`
+    #[arg(long, value_parser = parse_version_range)]
+    pub distributed_db_versions: Option<RangeInclusive<u16>>,
+    /// Supported p2p versions, a single version or a range like `0-1`
+    #[arg(long, value_parser = parse_version_range)]
+    pub p2p_versions: Option<RangeInclusive<u16>>,
+    /// Seconds allowed to open the TCP connection
+    #[arg(long, value_parser = parse_seconds)]
+    pub connect_timeout: Option<Duration>,
+    /// Seconds allowed to each handshake step
+    #[arg(long, value_parser = parse_seconds)]
+    pub handshake_timeout: Option<Duration>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Ghostnet,
+    Sandbox,
+}
+
+fn parse_version_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u16>()
+            .map_err(|e| format!("Invalid version '{}': {}", v, e))
+    };
+    let range = match s.split_once('-') {
+        Some((min, max)) => parse(min)?..=parse(max)?,
+        None => parse(s)?..=parse(s)?,
+    };
+    if range.is_empty() {
+        return Err(format!("Empty version range '{}'", s));
+    }
+    Ok(range)
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| *secs > 0.0)
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| {
+            format!(
+                "Invalid duration '{}', expected a positive number of seconds",
+                s
+            )
+        })
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Manage node identities
+    #[command(subcommand)]
+    Identity(IdentityCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IdentityCommand {
+    /// Generate a new identity and write it in the octez identity.json format
+    Generate {
+        /// Where to write the identity
+        #[arg(default_value = "identity.json")]
+        output: PathBuf,
+        /// Proof-of-work difficulty of the generated stamp
+        #[arg(long, default_value_t = DEFAULT_EXPECTED_POW)]
+        difficulty: f64,
+        /// Replace the output file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check an identity keys, peer id and proof-of-work stamp are consistent
+    Check {
+        /// Identity file to check
+        #[arg(default_value = "identity.json")]
+        path: PathBuf,
+        /// Proof-of-work difficulty the stamp must reach
+        #[arg(long, default_value_t = DEFAULT_EXPECTED_POW)]
+        expected_pow: f64,
+    },
+    /// Encrypt the secret key of a plain identity under a passphrase
+    Encrypt {
+        /// Identity file to encrypt
+        #[arg(default_value = "identity.json")]
+        path: PathBuf,
+        /// Where to write the encrypted identity, the identity file itself by default
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Replace the output file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Decrypt an encrypted identity back to the plain octez format
+    Decrypt {
+        /// Identity file to decrypt
+        #[arg(default_value = "identity.json")]
+        path: PathBuf,
+        /// Where to write the plain identity, the identity file itself by default
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Replace the output file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
 }
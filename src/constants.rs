@@ -1,9 +1,19 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
 pub const BOOTSTRAP_DEFAULT_PORT: u16 = 9732;
-pub const BOOTSTRAP_PEERS: &[&str] = &[
-    "boot.tzboot.net",
-    "boot.tzbeta.net",
-    "boot.mainnet.oxheadhosted.com",
-];
+
+/// Address we listen on when neither the command line nor config.json gives one
+pub const DEFAULT_LISTEN_ADDR: SocketAddr =
+    SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), BOOTSTRAP_DEFAULT_PORT);
+
+/// Handshakes tried when nodes refuse us and suggest other points
+pub const MAX_HANDSHAKE_ATTEMPTS: usize = 5;
+
+/// Incoming connections accepted before refusing new peers, as octez does
+pub const DEFAULT_MAX_CONNECTIONS: usize = 100;
+
+/// Proof-of-work difficulty octez expects from peers by default
+pub const DEFAULT_EXPECTED_POW: f64 = 26.0;
 
 pub const DEFAUL_IDENTITY_JSON: &str = r#"{ "peer_id": "idsfYM6UbG2nhNS1dqhsJEchaDhmd9",
   "public_key":
@@ -11,3 +21,13 @@ pub const DEFAUL_IDENTITY_JSON: &str = r#"{ "peer_id": "idsfYM6UbG2nhNS1dqhsJEch
   "secret_key":
     "0271fac86d020aebe6a1c9768381e7245e48e77524cca2a1652d0a621fac289f",
   "proof_of_work_stamp": "b6a4a80d765047918b037c85958c41096326a4b52ff0377e" }"#;
+
+/// Second identity (proof-of-work 26) for the remote side of test handshakes, so
+/// both ends derive their session keys from distinct key pairs
+#[cfg(test)]
+pub(crate) const REMOTE_IDENTITY_JSON: &str = r#"{ "peer_id": "idtCFAZinPMECvZCCcbUTX5HwSxn3C",
+  "public_key":
+    "325316ee17d831da7eea43044f3768c0db90423fc0e419c352202bd983cdd649",
+  "secret_key":
+    "31bfae9921323800fe43ad598d1ed08e1dd2b9268814efc17926d6f6c3680920",
+  "proof_of_work_stamp": "bc9a666d1bf4ec958bc36693ec0e89efa5e09ea1155aaecc" }"#;
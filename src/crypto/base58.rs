@@ -0,0 +1,80 @@
+use sodiumoxide::crypto::hash::sha256;
+use thiserror::Error;
+
+const CHECKSUM_SIZE: usize = 4;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum FromBase58CheckError {
+    #[error("Invalid base58 string")]
+    InvalidBase58,
+    #[error("Base58 string is too short to contain a checksum")]
+    MissingChecksum,
+    #[error("Base58 checksum mismatch")]
+    InvalidChecksum,
+    #[error("Unexpected base58check prefix")]
+    InvalidPrefix,
+    #[error("Invalid decoded size, expected: {expected}, actual: {actual}")]
+    InvalidSize { expected: usize, actual: usize },
+}
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let hash = sha256::hash(sha256::hash(data).as_ref());
+    let mut result = [0u8; CHECKSUM_SIZE];
+    result.copy_from_slice(&hash.as_ref()[..CHECKSUM_SIZE]);
+    result
+}
+
+/// Encodes data as base58 followed by the first 4 bytes of its double sha256.
+pub fn to_base58check(data: &[u8]) -> String {
+    let mut payload = Vec::with_capacity(data.len() + CHECKSUM_SIZE);
+    payload.extend_from_slice(data);
+    payload.extend_from_slice(&checksum(data));
+    bs58::encode(payload).into_string()
+}
+
+/// Decodes a base58check string, returning the data without its checksum.
+pub fn from_base58check(data: &str) -> Result<Vec<u8>, FromBase58CheckError> {
+    let mut payload = bs58::decode(data)
+        .into_vec()
+        .map_err(|_| FromBase58CheckError::InvalidBase58)?;
+    if payload.len() < CHECKSUM_SIZE {
+        return Err(FromBase58CheckError::MissingChecksum);
+    }
+
+    let expected = payload.split_off(payload.len() - CHECKSUM_SIZE);
+    if expected != checksum(&payload) {
+        return Err(FromBase58CheckError::InvalidChecksum);
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base58check_round_trip() {
+        let data = hex::decode("99679b62bc3b0a5a2e40bab7e0c4c35e").unwrap();
+        let encoded = to_base58check(&data);
+        assert_eq!(from_base58check(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_base58check_invalid_checksum() {
+        let mut encoded = to_base58check(b"hello world");
+        encoded.pop();
+        encoded.push('1');
+        assert_eq!(
+            from_base58check(&encoded),
+            Err(FromBase58CheckError::InvalidChecksum)
+        );
+    }
+
+    #[test]
+    fn test_base58check_invalid_characters() {
+        assert_eq!(
+            from_base58check("0OIl"),
+            Err(FromBase58CheckError::InvalidBase58)
+        );
+    }
+}
@@ -21,6 +21,11 @@ pub fn digest_256(data: &[u8]) -> Result<Vec<u8>, Blake2bError> {
     digest(data, 32)
 }
 
+/// Generate digest of length 128 bits (16bytes) from arbitrary binary data
+pub fn digest_128(data: &[u8]) -> Result<Vec<u8>, Blake2bError> {
+    digest(data, 16)
+}
+
 /// Arbitrary Blake2b digest generation from generic data.
 // Should be noted, that base Blake2b supports arbitrary digest length from 16 to 64 bytes
 pub fn digest(data: &[u8], out_len: usize) -> Result<Vec<u8>, Blake2bError> {
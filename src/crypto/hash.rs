@@ -0,0 +1,248 @@
+use super::base58::{from_base58check, to_base58check, FromBase58CheckError};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use speedy::{Context, Readable, Reader, Writable, Writer};
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+/// Error creating hash from bytes
+#[derive(Debug, Error, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+pub enum FromBytesError {
+    /// Invalid data size
+    #[error("Invalid hash size")]
+    InvalidSize,
+}
+
+/// Decodes a base58check string and strips its `prefix`, checking the hash size.
+fn decode_prefixed(s: &str, prefix: &[u8], size: usize) -> Result<Vec<u8>, FromBase58CheckError> {
+    let bytes = from_base58check(s)?;
+    let hash = bytes
+        .strip_prefix(prefix)
+        .ok_or(FromBase58CheckError::InvalidPrefix)?;
+    if hash.len() != size {
+        return Err(FromBase58CheckError::InvalidSize {
+            expected: size,
+            actual: hash.len(),
+        });
+    }
+    Ok(hash.to_vec())
+}
+
+/// Defines a fixed size hash displayed base58check encoded with its Tezos prefix.
+/// On the wire the hash is its raw bytes.
+macro_rules! define_hash {
+    ($(#[$doc:meta])* $name:ident, $prefix:expr, $size:expr) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+        pub struct $name([u8; $size]);
+
+        impl $name {
+            pub const SIZE: usize = $size;
+            const PREFIX: &'static [u8] = &$prefix;
+
+            pub fn from_bytes(bytes: &[u8]) -> Result<Self, FromBytesError> {
+                let bytes = bytes.try_into().map_err(|_| FromBytesError::InvalidSize)?;
+                Ok($name(bytes))
+            }
+
+            pub fn to_base58check(&self) -> String {
+                to_base58check(&[Self::PREFIX, &self.0].concat())
+            }
+        }
+
+        impl From<[u8; $size]> for $name {
+            fn from(bytes: [u8; $size]) -> Self {
+                $name(bytes)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = FromBase58CheckError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let hash = decode_prefixed(s, Self::PREFIX, Self::SIZE)?;
+                let mut bytes = [0u8; $size];
+                bytes.copy_from_slice(&hash);
+                Ok($name(bytes))
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.to_base58check())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_base58check())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                s.parse().map_err(de::Error::custom)
+            }
+        }
+
+        impl<'a, C: Context> Readable<'a, C> for $name {
+            fn read_from<R: Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+                let mut bytes = [0u8; $size];
+                reader.read_bytes(&mut bytes)?;
+                Ok($name(bytes))
+            }
+
+            fn minimum_bytes_needed() -> usize {
+                $size
+            }
+        }
+
+        impl<C: Context> Writable<C> for $name {
+            fn write_to<T: ?Sized + Writer<C>>(&self, writer: &mut T) -> Result<(), C::Error> {
+                writer.write_bytes(&self.0)
+            }
+        }
+    };
+}
+
+define_hash!(
+    /// Block hash, e.g. `BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2`
+    BlockHash,
+    [1, 52],
+    32
+);
+define_hash!(
+    /// Operation hash, they start with `o`
+    OperationHash,
+    [5, 116],
+    32
+);
+define_hash!(
+    /// Chain id, e.g. `NetXdQprcVkpaWU` for mainnet
+    ChainId,
+    [87, 82, 0],
+    4
+);
+define_hash!(
+    /// Protocol hash, they start with `P`
+    ProtocolHash,
+    [2, 170],
+    32
+);
+define_hash!(
+    /// Context hash, they start with `Co`
+    ContextHash,
+    [79, 199],
+    32
+);
+define_hash!(
+    /// Hash of the operation lists of a block, they start with `LLo`
+    OperationListListHash,
+    [29, 159, 109],
+    32
+);
+define_hash!(
+    /// Blake2b-128 hash of a crypto box public key, they start with `id`
+    CryptoboxPublicKeyHash,
+    [153, 103],
+    16
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_prefixes() {
+        fn check<H: fmt::Display>(low: H, high: H, expected: &str) {
+            assert!(low.to_string().starts_with(expected), "{}", low);
+            assert!(high.to_string().starts_with(expected), "{}", high);
+        }
+        check(BlockHash::from([0; 32]), BlockHash::from([0xff; 32]), "B");
+        check(
+            OperationHash::from([0; 32]),
+            OperationHash::from([0xff; 32]),
+            "o",
+        );
+        check(ChainId::from([0; 4]), ChainId::from([0xff; 4]), "Net");
+        check(
+            ProtocolHash::from([0; 32]),
+            ProtocolHash::from([0xff; 32]),
+            "P",
+        );
+        check(
+            ContextHash::from([0; 32]),
+            ContextHash::from([0xff; 32]),
+            "Co",
+        );
+        check(
+            OperationListListHash::from([0; 32]),
+            OperationListListHash::from([0xff; 32]),
+            "LLo",
+        );
+        check(
+            CryptoboxPublicKeyHash::from([0; 16]),
+            CryptoboxPublicKeyHash::from([0xff; 16]),
+            "id",
+        );
+    }
+
+    #[test]
+    fn test_hash_round_trip() {
+        let genesis = "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2";
+        assert_eq!(genesis.parse::<BlockHash>().unwrap().to_string(), genesis);
+
+        let mainnet = "NetXdQprcVkpaWU";
+        let chain_id: ChainId = mainnet.parse().unwrap();
+        assert_eq!(chain_id.as_ref(), [0x7a, 0x06, 0xa7, 0x70]);
+        assert_eq!(chain_id.to_string(), mainnet);
+    }
+
+    #[test]
+    fn test_hash_invalid_prefix() {
+        assert_eq!(
+            "NetXdQprcVkpaWU".parse::<BlockHash>(),
+            Err(FromBase58CheckError::InvalidPrefix)
+        );
+    }
+
+    #[test]
+    fn test_hash_invalid_size() {
+        let encoded = to_base58check(&[&[1, 52][..], &[0; 31]].concat());
+        assert_eq!(
+            encoded.parse::<BlockHash>(),
+            Err(FromBase58CheckError::InvalidSize {
+                expected: 32,
+                actual: 31
+            })
+        );
+    }
+
+    #[test]
+    fn test_hash_invalid_checksum() {
+        assert_eq!(
+            "NetXdQprcVkpaWV".parse::<ChainId>(),
+            Err(FromBase58CheckError::InvalidChecksum)
+        );
+    }
+
+    #[test]
+    fn test_hash_serde() {
+        let chain_id: ChainId = "NetXdQprcVkpaWU".parse().unwrap();
+        let json = serde_json::to_string(&chain_id).unwrap();
+        assert_eq!(json, "\"NetXdQprcVkpaWU\"");
+        assert_eq!(serde_json::from_str::<ChainId>(&json).unwrap(), chain_id);
+    }
+}
@@ -1,22 +1,25 @@
 use super::{
     blake2b::Blake2bError,
-    key::{PublicKey, SecretKey},
-    pow::ProofOfWork,
+    hash::FromBytesError,
+    key::{expose_secret_key_hex, random_keypair, wipe, PublicKey, SecretKey},
+    peer_id::PeerId,
+    pow::{PowError, ProofOfWork},
+    sealed::{SealError, SealedSecretKey},
 };
+use crate::passphrase;
 use hex::FromHex;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use serde_json::Value;
-use std::{collections::HashMap, io};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::AtomicBool,
+};
 use thiserror::Error;
 
-/// Error creating hash from bytes
-#[derive(Debug, Error, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
-pub enum FromBytesError {
-    /// Invalid data size
-    #[error("Invalid hash size")]
-    InvalidSize,
-}
-
 #[derive(Debug, Error, PartialEq)]
 pub enum PublicKeyError {
     #[error("Error constructing hash: {0}")]
@@ -35,13 +38,41 @@ pub enum IdentityError {
 
     #[error("Invalid field error, reason: {reason}")]
     IdentityFieldError { reason: String },
+
+    #[error("Proof-of-work error, reason: {reason}")]
+    IdentityPowError { reason: PowError },
+
+    #[error("Peer id error, reason: {reason}")]
+    IdentityPeerIdError { reason: PublicKeyError },
+
+    #[error("Peer id {found} does not match the public key, expected: {expected}")]
+    PeerIdMismatch { expected: PeerId, found: PeerId },
+
+    #[error("Public key {found} does not match the secret key, expected: {expected}")]
+    KeyPairMismatch { expected: String, found: String },
+
+    #[error("Proof-of-work stamp is below the expected difficulty {expected}")]
+    InsufficientProofOfWork { expected: f64 },
+
+    #[error("Identity secret key is encrypted, a passphrase is needed")]
+    EncryptedIdentity,
+
+    #[error("Encrypted secret key error, reason: {reason}")]
+    IdentitySealError { reason: SealError },
+
+    #[error("Passphrase error, reason: {reason}")]
+    PassphraseError { reason: io::Error },
+
+    #[error("Identity file {path} already exists")]
+    AlreadyExists { path: PathBuf },
 }
 
-/// This node identity information compatible with Tezos
-#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+/// This node identity information compatible with Tezos, its secret key is
+/// redacted from `Debug` and only written by [`Identity::to_json`]
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Identity {
     /// Peer_id is calculated hash of public_key [`crypto_box::PublicKey`]
-    pub peer_id: String,
+    pub peer_id: PeerId,
     /// Hex encoded public key: [`crypto_box::PublicKey`]
     pub public_key: PublicKey,
     /// Hex encoded secret key: [`crypto_box::SecretKey`]
@@ -50,11 +81,189 @@ pub struct Identity {
     pub proof_of_work_stamp: ProofOfWork,
 }
 
+/// Field order of octez `identity.json` files
+#[derive(Serialize)]
+struct IdentityJson<'a> {
+    peer_id: PeerId,
+    public_key: String,
+    #[serde(serialize_with = "expose_secret_key_hex")]
+    secret_key: &'a SecretKey,
+    proof_of_work_stamp: String,
+}
+
+/// Same layout, with the secret key sealed under a passphrase
+#[derive(Serialize)]
+struct EncryptedIdentityJson {
+    peer_id: PeerId,
+    public_key: String,
+    encrypted_secret_key: SealedSecretKey,
+    proof_of_work_stamp: String,
+}
+
+const ENCRYPTED_SECRET_KEY: &str = "encrypted_secret_key";
+
 impl Identity {
+    /// Generates a new identity whose proof-of-work stamp reaches `target_difficulty`.
+    pub fn generate(target_difficulty: f64) -> Result<Identity, IdentityError> {
+        Identity::generate_with(target_difficulty, &AtomicBool::new(false), |_| {})
+    }
+
+    /// Same as [`Identity::generate`], reporting the number of attempts to `progress`
+    /// and stopping early once `cancel` is set.
+    pub fn generate_with<F: Fn(u64)>(
+        target_difficulty: f64,
+        cancel: &AtomicBool,
+        progress: F,
+    ) -> Result<Identity, IdentityError> {
+        let (public_key, secret_key) = random_keypair();
+        let peer_id = PeerId::from_public_key(&public_key)
+            .map_err(|e| IdentityError::IdentityPeerIdError { reason: e })?;
+        let proof_of_work_stamp =
+            ProofOfWork::generate(&public_key, target_difficulty, cancel, progress)
+                .map_err(|e| IdentityError::IdentityPowError { reason: e })?;
+
+        Ok(Identity {
+            peer_id,
+            public_key,
+            secret_key,
+            proof_of_work_stamp,
+        })
+    }
+
+    /// Checks the identity is consistent: the public key and peer id derive from the
+    /// secret key and the proof-of-work stamp reaches `expected_pow`.
+    pub fn validate(&self, expected_pow: f64) -> Result<(), IdentityError> {
+        let public_key = self.secret_key.public_key();
+        if public_key != self.public_key {
+            return Err(IdentityError::KeyPairMismatch {
+                expected: hex::encode(public_key.as_ref()),
+                found: hex::encode(self.public_key.as_ref()),
+            });
+        }
+
+        let peer_id = PeerId::from_public_key(&public_key)
+            .map_err(|e| IdentityError::IdentityPeerIdError { reason: e })?;
+        if peer_id != self.peer_id {
+            return Err(IdentityError::PeerIdMismatch {
+                expected: peer_id,
+                found: self.peer_id,
+            });
+        }
+
+        match self.proof_of_work_stamp.check(&public_key, expected_pow) {
+            Ok(()) => Ok(()),
+            Err(PowError::CheckFailed) => Err(IdentityError::InsufficientProofOfWork {
+                expected: expected_pow,
+            }),
+            Err(e) => Err(IdentityError::IdentityPowError { reason: e }),
+        }
+    }
+
+    /// Serializes the identity in the octez `identity.json` format, the secret key
+    /// in clear: [`wipe`] the result once written.
+    pub fn to_json(&self) -> Result<String, IdentityError> {
+        let json = IdentityJson {
+            peer_id: self.peer_id,
+            public_key: hex::encode(self.public_key.as_ref()),
+            secret_key: &self.secret_key,
+            proof_of_work_stamp: hex::encode(self.proof_of_work_stamp.as_ref()),
+        };
+        serde_json::to_string_pretty(&json)
+            .map_err(|e| IdentityError::IdentitySerdeError { reason: e })
+    }
+
+    /// Serializes the identity like [`Identity::to_json`], with the secret key
+    /// encrypted under `passphrase`.
+    pub fn to_encrypted_json(&self, passphrase: &str) -> Result<String, IdentityError> {
+        let encrypted_secret_key = SealedSecretKey::seal(&self.secret_key, passphrase.as_bytes())
+            .map_err(|e| IdentityError::IdentitySealError { reason: e })?;
+        let json = EncryptedIdentityJson {
+            peer_id: self.peer_id,
+            public_key: hex::encode(self.public_key.as_ref()),
+            encrypted_secret_key,
+            proof_of_work_stamp: hex::encode(self.proof_of_work_stamp.as_ref()),
+        };
+        serde_json::to_string_pretty(&json)
+            .map_err(|e| IdentityError::IdentitySerdeError { reason: e })
+    }
+
+    /// Writes the identity to `path` in the octez `identity.json` format, readable by
+    /// its owner only. The file is written next to `path` then moved to it, so a
+    /// reader never sees a partial identity. An existing file is kept unless `force`,
+    /// including one created while writing.
+    pub fn to_json_file(&self, path: &Path, force: bool) -> Result<(), IdentityError> {
+        ensure_writable(path, force)?;
+        let json = self.to_json()?;
+        let result = write_identity_file(path, &json, force);
+        wipe(json);
+        result
+    }
+
+    /// Same as [`Identity::to_json_file`], with the secret key encrypted under `passphrase`.
+    pub fn to_encrypted_json_file(
+        &self,
+        path: &Path,
+        passphrase: &str,
+        force: bool,
+    ) -> Result<(), IdentityError> {
+        ensure_writable(path, force)?;
+        write_identity_file(path, &self.to_encrypted_json(passphrase)?, force)
+    }
+
+    /// Whether the identity secret key is encrypted, so a passphrase is needed to load it
+    pub fn is_encrypted_json(json: &str) -> bool {
+        serde_json::from_str::<HashMap<String, Value>>(json)
+            .is_ok_and(|identity| identity.contains_key(ENCRYPTED_SECRET_KEY))
+    }
+
+    /// Parses a plain identity, an encrypted one fails with [`IdentityError::EncryptedIdentity`].
     pub fn from_json(json: &str) -> Result<Identity, IdentityError> {
-        let identity: HashMap<String, Value> = serde_json::from_str(json)
+        Identity::from_json_with(json, |_| Err(IdentityError::EncryptedIdentity))
+    }
+
+    /// Parses a plain or an encrypted identity, opening the latter with `passphrase`.
+    pub fn from_encrypted_json(json: &str, passphrase: &str) -> Result<Identity, IdentityError> {
+        Identity::from_json_with(json, |sealed| {
+            sealed
+                .open(passphrase.as_bytes())
+                .map_err(|e| IdentityError::IdentitySealError { reason: e })
+        })
+    }
+
+    /// Parses a plain or an encrypted identity, the passphrase of the latter is read
+    /// as described in [`passphrase::read_passphrase`].
+    pub fn from_json_or_prompt(json: &str) -> Result<Identity, IdentityError> {
+        Identity::from_json_with(json, |sealed| {
+            let passphrase = passphrase::read_passphrase("Identity passphrase: ")
+                .map_err(|e| IdentityError::PassphraseError { reason: e })?;
+            let secret_key = sealed
+                .open(passphrase.as_bytes())
+                .map_err(|e| IdentityError::IdentitySealError { reason: e });
+            wipe(passphrase);
+            secret_key
+        })
+    }
+
+    fn from_json_with<F>(json: &str, open: F) -> Result<Identity, IdentityError>
+    where
+        F: FnOnce(SealedSecretKey) -> Result<SecretKey, IdentityError>,
+    {
+        let mut identity: HashMap<String, Value> = serde_json::from_str(json)
             .map_err(|e| IdentityError::IdentitySerdeError { reason: e })?;
+        let result = Identity::from_json_fields(&identity, open);
+        if let Some(Value::String(secret_key)) = identity.remove("secret_key") {
+            wipe(secret_key);
+        }
+        result
+    }
 
+    fn from_json_fields<F>(
+        identity: &HashMap<String, Value>,
+        open: F,
+    ) -> Result<Identity, IdentityError>
+    where
+        F: FnOnce(SealedSecretKey) -> Result<SecretKey, IdentityError>,
+    {
         let peer_id_str = identity
             .get("peer_id")
             .ok_or(IdentityError::IdentityFieldError {
@@ -64,7 +273,10 @@ impl Identity {
             .ok_or(IdentityError::IdentityFieldError {
                 reason: "Missing valid 'peer_id'".to_string(),
             })?;
-        let peer_id = peer_id_str.to_string();
+        let peer_id =
+            PeerId::from_str(peer_id_str).map_err(|e| IdentityError::IdentityFieldError {
+                reason: format!("Missing valid 'peer_id', reason: {}", e),
+            })?;
         let public_key_str = identity
             .get("public_key")
             .ok_or(IdentityError::IdentityFieldError {
@@ -79,19 +291,30 @@ impl Identity {
                 reason: format!("Missing valid 'public_key', reason: {}", e),
             })?;
 
-        let secret_key_str = identity
-            .get("secret_key")
-            .ok_or(IdentityError::IdentityFieldError {
-                reason: "Missing 'secret_key'".to_string(),
-            })?
-            .as_str()
-            .ok_or(IdentityError::IdentityFieldError {
-                reason: "Missing valid 'secret_key'".to_string(),
+        let secret_key = if let Some(sealed) = identity.get(ENCRYPTED_SECRET_KEY) {
+            let sealed = serde_json::from_value(sealed.clone()).map_err(|e| {
+                IdentityError::IdentityFieldError {
+                    reason: format!("Missing valid '{}', reason: {}", ENCRYPTED_SECRET_KEY, e),
+                }
             })?;
-        let secret_key =
-            SecretKey::from_hex(secret_key_str).map_err(|e| IdentityError::IdentityFieldError {
-                reason: format!("Missing valid 'secret_key', reason: {}", e),
+            open(sealed)?
+        } else {
+            let secret_key_str = identity
+                .get("secret_key")
+                .ok_or(IdentityError::IdentityFieldError {
+                    reason: "Missing 'secret_key'".to_string(),
+                })?
+                .as_str()
+                .ok_or(IdentityError::IdentityFieldError {
+                    reason: "Missing valid 'secret_key'".to_string(),
+                })?;
+            let secret_key = SecretKey::from_hex(secret_key_str).map_err(|e| {
+                IdentityError::IdentityFieldError {
+                    reason: format!("Missing valid 'secret_key', reason: {}", e),
+                }
             })?;
+            secret_key
+        };
 
         let proof_of_work_stamp_str = identity
             .get("proof_of_work_stamp")
@@ -108,6 +331,15 @@ impl Identity {
             }
         })?;
 
+        let expected = PeerId::from_public_key(&public_key)
+            .map_err(|e| IdentityError::IdentityPeerIdError { reason: e })?;
+        if peer_id != expected {
+            return Err(IdentityError::PeerIdMismatch {
+                expected,
+                found: peer_id,
+            });
+        }
+
         Ok(Identity {
             peer_id,
             public_key,
@@ -116,22 +348,79 @@ impl Identity {
         })
     }
 
+    /// Reads a plain or an encrypted identity file, see [`Identity::from_json_or_prompt`].
     pub fn from_json_file(identity_path: std::path::PathBuf) -> Result<Identity, IdentityError> {
         let json = std::fs::read_to_string(identity_path).map_err(|e| IdentityError::IoError {
-            reason: io::Error::new(io::ErrorKind::Other, e),
+            reason: io::Error::other(e),
         })?;
-        Identity::from_json(&json)
+        let identity = Identity::from_json_or_prompt(&json);
+        wipe(json);
+        identity
     }
 }
 
+fn ensure_writable(path: &Path, force: bool) -> Result<(), IdentityError> {
+    if !force && path.exists() {
+        return Err(IdentityError::AlreadyExists {
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+/// Writes `json` to a temporary file next to `path` then renames it over `path`
+/// when `force`, otherwise hard links it to `path`, which fails if `path` exists.
+fn write_identity_file(path: &Path, json: &str, force: bool) -> Result<(), IdentityError> {
+    let file_name = path.file_name().ok_or(IdentityError::IoError {
+        reason: io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Identity path has no file name",
+        ),
+    })?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = write_private_file(&tmp_path, json.as_bytes()).and_then(|()| {
+        if force {
+            fs::rename(&tmp_path, path)
+        } else {
+            fs::hard_link(&tmp_path, path)
+        }
+    });
+    if result.is_err() || !force {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result.map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => IdentityError::AlreadyExists {
+            path: path.to_path_buf(),
+        },
+        _ => IdentityError::IoError { reason: e },
+    })
+}
+
+/// Creates a new file only its owner can read and write, flushed to disk
+fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::{key::PrecomputedKey, peer_crypto::PeerCrypto};
 
     // Helper function to create a sample identity for testing
     fn sample_identity() -> Identity {
         Identity {
-            peer_id: "idsfYM6UbG2nhNS1dqhsJEchaDhmd9".to_string(),
+            peer_id: "idsfYM6UbG2nhNS1dqhsJEchaDhmd9".parse().unwrap(),
             public_key: PublicKey::from_hex(
                 "17f7d11892274a7230d969aa1335d25e637f43087b76d0e24a1a8b7d03168f5c",
             )
@@ -161,4 +450,207 @@ mod tests {
         let identity = result.unwrap();
         assert_eq!(identity, sample_identity());
     }
+
+    #[test]
+    fn test_identity_from_json_peer_id_mismatch() {
+        let json: &str = r#"{ "peer_id": "idryxBR8NH1K11pcuyP3oietPfd1FM",
+  "public_key":
+    "17f7d11892274a7230d969aa1335d25e637f43087b76d0e24a1a8b7d03168f5c",
+  "secret_key":
+    "0271fac86d020aebe6a1c9768381e7245e48e77524cca2a1652d0a621fac289f",
+  "proof_of_work_stamp": "b6a4a80d765047918b037c85958c41096326a4b52ff0377e" }"#;
+        assert!(matches!(
+            Identity::from_json(json),
+            Err(IdentityError::PeerIdMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_identity_to_json_round_trip() {
+        let identity = sample_identity();
+        let json = identity.to_json().unwrap();
+        assert_eq!(Identity::from_json(&json).unwrap(), identity);
+    }
+
+    #[test]
+    fn test_identity_validate() {
+        let identity = sample_identity();
+        assert!(identity.validate(0.0).is_ok());
+        assert!(matches!(
+            identity.validate(200.0),
+            Err(IdentityError::InsufficientProofOfWork { expected }) if expected == 200.0
+        ));
+    }
+
+    #[test]
+    fn test_identity_validate_key_pair_mismatch() {
+        let (_, secret_key) = random_keypair();
+        let identity = Identity {
+            secret_key,
+            ..sample_identity()
+        };
+        assert!(matches!(
+            identity.validate(0.0),
+            Err(IdentityError::KeyPairMismatch { found, .. })
+                if found == "17f7d11892274a7230d969aa1335d25e637f43087b76d0e24a1a8b7d03168f5c"
+        ));
+    }
+
+    #[test]
+    fn test_identity_validate_peer_id_mismatch() {
+        let identity = Identity {
+            peer_id: "idryxBR8NH1K11pcuyP3oietPfd1FM".parse().unwrap(),
+            ..sample_identity()
+        };
+        assert!(matches!(
+            identity.validate(0.0),
+            Err(IdentityError::PeerIdMismatch { expected, .. })
+                if expected == sample_identity().peer_id
+        ));
+    }
+
+    #[test]
+    fn test_identity_to_json_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.json");
+        let identity = sample_identity();
+        identity.to_json_file(&path, false).unwrap();
+
+        let json = fs::read_to_string(&path).unwrap();
+        let fields: Vec<&str> = json
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix('"')?.split('"').next())
+            .collect();
+        assert_eq!(
+            fields,
+            ["peer_id", "public_key", "secret_key", "proof_of_work_stamp"]
+        );
+        assert_eq!(Identity::from_json_file(path.clone()).unwrap(), identity);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_identity_to_json_file_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.json");
+        fs::write(&path, "previous").unwrap();
+
+        let identity = sample_identity();
+        assert!(matches!(
+            identity.to_json_file(&path, false),
+            Err(IdentityError::AlreadyExists { .. })
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous");
+
+        identity.to_json_file(&path, true).unwrap();
+        assert_eq!(Identity::from_json_file(path).unwrap(), identity);
+    }
+
+    #[test]
+    fn test_write_identity_file_created_meanwhile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.json");
+        // Created after the early existence check, the write itself must not replace it
+        fs::write(&path, "previous").unwrap();
+
+        assert!(matches!(
+            write_identity_file(&path, "{}", false),
+            Err(IdentityError::AlreadyExists { .. })
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_identity_encrypted_json() {
+        let identity = sample_identity();
+        let json = identity.to_encrypted_json("correct horse").unwrap();
+        assert!(Identity::is_encrypted_json(&json));
+        assert!(!json.contains("0271fac86d020aebe6a1c9768381e7245e48e77524cca2a1652d0a621fac289f"));
+
+        assert!(matches!(
+            Identity::from_json(&json),
+            Err(IdentityError::EncryptedIdentity)
+        ));
+        assert!(matches!(
+            Identity::from_encrypted_json(&json, "battery staple"),
+            Err(IdentityError::IdentitySealError {
+                reason: SealError::WrongPassphrase
+            })
+        ));
+        assert_eq!(
+            Identity::from_encrypted_json(&json, "correct horse").unwrap(),
+            identity
+        );
+    }
+
+    #[test]
+    fn test_identity_plain_json_is_not_encrypted() {
+        let json = sample_identity().to_json().unwrap();
+        assert!(!Identity::is_encrypted_json(&json));
+        assert_eq!(
+            Identity::from_encrypted_json(&json, "unused").unwrap(),
+            sample_identity()
+        );
+    }
+
+    #[test]
+    fn test_secrets_redacted_from_debug() {
+        let identity = sample_identity();
+        let secret_hex = "0271fac86d020aebe6a1c9768381e7245e48e77524cca2a1652d0a621fac289f";
+        let secret_bytes = format!("{:?}", identity.secret_key.as_ref().0);
+        let peer_crypto = PeerCrypto::build(
+            &identity.secret_key,
+            &identity.public_key,
+            vec![1, 2, 3],
+            vec![4, 5, 6],
+            false,
+        )
+        .unwrap();
+        let precomputed_key =
+            PrecomputedKey::precompute(&identity.public_key, &identity.secret_key);
+        let precomputed_bytes = format!(
+            "{:?}",
+            sodiumoxide::crypto::box_::precompute(
+                identity.public_key.as_ref(),
+                identity.secret_key.as_ref()
+            )
+            .0
+        );
+
+        let outputs = [
+            format!("{:?}", identity),
+            format!("{:#?}", identity),
+            format!("{:?}", identity.secret_key),
+            format!("{:?}", peer_crypto),
+            format!("{:#?}", peer_crypto),
+            format!("{:?}", precomputed_key),
+        ];
+        for output in outputs {
+            assert!(!output.contains(secret_hex), "{}", output);
+            assert!(!output.contains(&secret_bytes), "{}", output);
+            assert!(!output.contains(&precomputed_bytes), "{}", output);
+        }
+        assert!(format!("{:?}", identity).contains("SecretKey(<redacted>)"));
+    }
+
+    #[test]
+    fn test_identity_generate() {
+        let identity = Identity::generate(8.0).unwrap();
+        assert_eq!(
+            PeerId::from_public_key(&identity.public_key).unwrap(),
+            identity.peer_id
+        );
+        assert!(identity
+            .proof_of_work_stamp
+            .check(&identity.public_key, 8.0)
+            .is_ok());
+    }
 }
@@ -3,9 +3,9 @@ use super::{
     nonce::Nonce,
 };
 use hex::{FromHex, FromHexError};
-use serde::{Deserialize, Serialize};
-use sodiumoxide::crypto::box_;
-use std::fmt::Debug;
+use serde::{Deserialize, Serialize, Serializer};
+use sodiumoxide::{crypto::box_, utils::memzero};
+use std::fmt::{self, Debug};
 use thiserror::Error;
 
 pub const BOX_ZERO_BYTES: usize = 32;
@@ -46,6 +46,13 @@ fn ensure_crypto_key_bytes<B: AsRef<[u8]>>(buf: B) -> Result<[u8; CRYPTO_KEY_SIZ
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct PublicKey(box_::PublicKey);
 
+impl PublicKey {
+    /// Blake2b-128 hash of the key, the raw form of a peer id
+    pub fn public_key_hash(&self) -> Result<Vec<u8>, Blake2bError> {
+        blake2b::digest_128(self.0.as_ref())
+    }
+}
+
 impl CryptoKey for PublicKey {
     fn from_bytes<B: AsRef<[u8]>>(buf: B) -> Result<Self, CryptoError> {
         ensure_crypto_key_bytes(buf).map(|key_bytes| PublicKey(box_::PublicKey(key_bytes)))
@@ -66,10 +73,25 @@ impl FromHex for PublicKey {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
-/// Convenience wrapper around [`sodiumoxide::crypto::box_::SecretKey`]
+/// Convenience wrapper around [`sodiumoxide::crypto::box_::SecretKey`].
+/// The key is zeroed on drop, redacted from `Debug` and only serialized
+/// through [`expose_secret_key_hex`].
+#[derive(Clone, Eq, PartialEq)]
 pub struct SecretKey(box_::SecretKey);
 
+impl Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(<redacted>)")
+    }
+}
+
+impl SecretKey {
+    /// Derives the public key paired with this secret key
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.0.public_key())
+    }
+}
+
 impl CryptoKey for SecretKey {
     fn from_bytes<B: AsRef<[u8]>>(buf: B) -> Result<Self, CryptoError> {
         ensure_crypto_key_bytes(buf).map(|key_bytes| SecretKey(box_::SecretKey(key_bytes)))
@@ -86,14 +108,48 @@ impl FromHex for SecretKey {
     type Error = CryptoError;
 
     fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self, Self::Error> {
-        Self::from_bytes(hex::decode(hex)?)
+        let bytes = hex::decode(hex)?;
+        let key = Self::from_bytes(&bytes);
+        wipe(bytes);
+        key
     }
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
-/// Convenience wrapper around [`sodiumoxide::crypto::box_::PrecomputedKey`]
+/// Serializes a secret key as hex, secrets are only written where a field opts in
+/// with `#[serde(serialize_with = "expose_secret_key_hex")]`.
+pub fn expose_secret_key_hex<S: Serializer>(
+    key: &SecretKey,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let hex = hex::encode(key.as_ref());
+    let result = serializer.serialize_str(&hex);
+    wipe(hex);
+    result
+}
+
+/// Zeroes a buffer that held secret material before freeing it
+pub fn wipe<T: Into<Vec<u8>>>(secret: T) {
+    let mut bytes = secret.into();
+    memzero(&mut bytes);
+}
+
+/// Generates a new random curve25519 key pair
+pub fn random_keypair() -> (PublicKey, SecretKey) {
+    let (pk, sk) = box_::gen_keypair();
+    (PublicKey(pk), SecretKey(sk))
+}
+
+/// Convenience wrapper around [`sodiumoxide::crypto::box_::PrecomputedKey`],
+/// zeroed on drop and redacted from `Debug` like [`SecretKey`]
+#[derive(Eq, PartialEq, Clone)]
 pub struct PrecomputedKey(box_::PrecomputedKey);
 
+impl Debug for PrecomputedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PrecomputedKey(<redacted>)")
+    }
+}
+
 impl PrecomputedKey {
     pub fn precompute(pk: &PublicKey, sk: &SecretKey) -> Self {
         Self(box_::precompute(pk.as_ref(), sk.as_ref()))
@@ -1,6 +1,10 @@
+pub mod base58;
 pub mod blake2b;
+pub mod hash;
 pub mod key;
 pub mod pow;
 pub mod peer_crypto;
 pub mod nonce;
 pub mod identity;
+pub mod peer_id;
+pub mod sealed;
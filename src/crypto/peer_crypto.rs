@@ -62,5 +62,103 @@ impl PeerCrypto {
         let nonce = self.remote_nonce_fetch_increment();
         self.precomputed_key.decrypt(data.as_ref(), &nonce)
     }
+
+    /// Splits into a half encrypting with the local nonce and a half decrypting
+    /// with the remote one. Each nonce sequence is owned by a single half.
+    pub fn split(self) -> (PeerCryptoWriter, PeerCryptoReader) {
+        let NoncePair { local, remote } = self.nonce_pair;
+        (
+            PeerCryptoWriter {
+                precomputed_key: self.precomputed_key.clone(),
+                local_nonce: local,
+            },
+            PeerCryptoReader {
+                precomputed_key: self.precomputed_key,
+                remote_nonce: remote,
+            },
+        )
+    }
+}
+
+/// Encrypting half of a [`PeerCrypto`], for the task writing to the peer
+#[derive(Debug)]
+pub struct PeerCryptoWriter {
+    precomputed_key: PrecomputedKey,
+    local_nonce: Nonce,
 }
 
+impl PeerCryptoWriter {
+    /// Increments local nonce and encrypts the message.
+    pub fn encrypt<T: AsRef<[u8]>>(&mut self, data: &T) -> Result<Vec<u8>, CryptoError> {
+        let nonce = self.local_nonce.increment();
+        let nonce = std::mem::replace(&mut self.local_nonce, nonce);
+        self.precomputed_key.encrypt(data.as_ref(), &nonce)
+    }
+}
+
+/// Decrypting half of a [`PeerCrypto`], for the task reading from the peer
+#[derive(Debug)]
+pub struct PeerCryptoReader {
+    precomputed_key: PrecomputedKey,
+    remote_nonce: Nonce,
+}
+
+impl PeerCryptoReader {
+    /// Increments remote nonce and decrypts the message.
+    pub fn decrypt<T: AsRef<[u8]>>(&mut self, data: &T) -> Result<Vec<u8>, CryptoError> {
+        let nonce = self.remote_nonce.increment();
+        let nonce = std::mem::replace(&mut self.remote_nonce, nonce);
+        self.precomputed_key.decrypt(data.as_ref(), &nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key::random_keypair;
+
+    fn crypto_pair() -> (PeerCrypto, PeerCrypto) {
+        let (initiator_pk, initiator_sk) = random_keypair();
+        let (responder_pk, responder_sk) = random_keypair();
+        let (sent, received) = (vec![1, 2, 3], vec![4, 5, 6]);
+        let initiator = PeerCrypto::build(
+            &initiator_sk,
+            &responder_pk,
+            sent.clone(),
+            received.clone(),
+            false,
+        )
+        .unwrap();
+        let responder =
+            PeerCrypto::build(&responder_sk, &initiator_pk, received, sent, true).unwrap();
+        (initiator, responder)
+    }
+
+    #[test]
+    fn test_split_halves_follow_nonce_sequences() {
+        let (mut initiator, responder) = crypto_pair();
+        // A message sent before the split moves the local nonce forward
+        let first = initiator.encrypt(b"before split").unwrap();
+
+        let (mut initiator_writer, mut initiator_reader) = initiator.split();
+        let (mut responder_writer, mut responder_reader) = responder.split();
+        assert_eq!(responder_reader.decrypt(&first).unwrap(), b"before split");
+
+        for i in 0..3u8 {
+            let sent = initiator_writer.encrypt(&[i]).unwrap();
+            assert_eq!(responder_reader.decrypt(&sent).unwrap(), [i]);
+            let answer = responder_writer.encrypt(&[i, i]).unwrap();
+            assert_eq!(initiator_reader.decrypt(&answer).unwrap(), [i, i]);
+        }
+    }
+
+    #[test]
+    fn test_split_reader_rejects_replayed_message() {
+        let (initiator, responder) = crypto_pair();
+        let (mut writer, _) = initiator.split();
+        let (_, mut reader) = responder.split();
+        let sent = writer.encrypt(b"once").unwrap();
+        assert!(reader.decrypt(&sent).is_ok());
+        assert!(reader.decrypt(&sent).is_err());
+    }
+}
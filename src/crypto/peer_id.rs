@@ -0,0 +1,46 @@
+use super::{hash::CryptoboxPublicKeyHash, identity::PublicKeyError, key::PublicKey};
+
+/// Peer id is the blake2b-128 hash of a node public key,
+/// displayed base58check encoded, e.g. `idsfYM6UbG2nhNS1dqhsJEchaDhmd9`
+pub type PeerId = CryptoboxPublicKeyHash;
+
+impl CryptoboxPublicKeyHash {
+    pub fn from_public_key(public_key: &PublicKey) -> Result<Self, PublicKeyError> {
+        Ok(PeerId::from_bytes(&public_key.public_key_hash()?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::base58::{to_base58check, FromBase58CheckError};
+    use hex::FromHex;
+
+    #[test]
+    fn test_peer_id_from_public_key() {
+        let public_key =
+            PublicKey::from_hex("17f7d11892274a7230d969aa1335d25e637f43087b76d0e24a1a8b7d03168f5c")
+                .unwrap();
+        let peer_id = PeerId::from_public_key(&public_key).unwrap();
+        assert_eq!(peer_id.to_string(), "idsfYM6UbG2nhNS1dqhsJEchaDhmd9");
+    }
+
+    #[test]
+    fn test_peer_id_round_trip() {
+        let peer_id: PeerId = "idsfYM6UbG2nhNS1dqhsJEchaDhmd9".parse().unwrap();
+        assert_eq!(
+            peer_id.as_ref(),
+            hex::decode("a0bc6b2244e1c4e2048ccc5bcc6c8bbf").unwrap()
+        );
+        assert_eq!(peer_id.to_string(), "idsfYM6UbG2nhNS1dqhsJEchaDhmd9");
+    }
+
+    #[test]
+    fn test_peer_id_invalid_prefix() {
+        let encoded = to_base58check(&[0u8; PeerId::SIZE + 2]);
+        assert_eq!(
+            encoded.parse::<PeerId>(),
+            Err(FromBase58CheckError::InvalidPrefix)
+        );
+    }
+}
@@ -1,6 +1,17 @@
-use super::{blake2b::Blake2bError, key::CryptoError, nonce::NONCE_SIZE};
+use super::{
+    blake2b::{self, Blake2bError},
+    key::{CryptoError, PublicKey},
+    nonce::{Nonce, NONCE_SIZE},
+};
 use hex::FromHex;
 use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
 pub const POW_SIZE: usize = NONCE_SIZE;
@@ -11,25 +22,119 @@ pub enum PowError {
     CheckFailed,
     #[error("Proof-of-work blake2b error: {0}")]
     Blake2b(Blake2bError),
+    #[error("Proof-of-work generation cancelled")]
+    Cancelled,
+}
+
+impl From<Blake2bError> for PowError {
+    fn from(e: Blake2bError) -> Self {
+        PowError::Blake2b(e)
+    }
 }
 
 pub type PowResult = Result<(), PowError>;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
-pub struct ProofOfWork([u8; POW_SIZE]);
+const TARGET_SIZE: usize = 32;
+const MANTISSA_BITS: u32 = 48;
+/// Attempts made by a worker between two updates of the shared counter
+const ATTEMPTS_BATCH: u64 = 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
 
-impl AsRef<[u8]> for ProofOfWork {
-    fn as_ref(&self) -> &[u8] {
-        &self.0
+/// Builds the 256 bits big-endian target for a given difficulty,
+/// the same way octez `Crypto_box.make_target` does.
+fn make_target(target: f64) -> [u8; TARGET_SIZE] {
+    let target = target.clamp(0.0, 256.0);
+    let shift = target.trunc();
+    let frac = target - shift;
+    let mantissa: u64 = if frac == 0.0 {
+        (1 << MANTISSA_BITS) - 1
+    } else {
+        2.0_f64.powf(MANTISSA_BITS as f64 - frac) as u64
+    };
+
+    // (mantissa << (256 - 48 - shift)) with every lower bit set,
+    // bits shifted out of the 256 bits range are dropped
+    let offset = 256 - MANTISSA_BITS as i64 - shift as i64;
+    let mut result = [0u8; TARGET_SIZE];
+    for pos in 0..(TARGET_SIZE * 8) as i64 {
+        let bit = pos - offset;
+        let set = if bit < 0 {
+            true
+        } else {
+            bit < MANTISSA_BITS as i64 && mantissa & (1 << bit) != 0
+        };
+        if set {
+            result[TARGET_SIZE - 1 - (pos / 8) as usize] |= 1 << (pos % 8);
+        }
     }
+    result
 }
 
-impl FromHex for ProofOfWork {
-    type Error = CryptoError;
+/// Checks that the blake2b hash of `data` is below the target derived from `target` difficulty.
+// octez reads the hash as a little-endian number, so it is reversed before comparing
+pub fn check_proof_of_work(data: &[u8], target: f64) -> PowResult {
+    check_target(data, &make_target(target))
+}
 
-    fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self, Self::Error> {
-        let bytes = hex::decode(hex)?;
+fn check_target(data: &[u8], target: &[u8; TARGET_SIZE]) -> PowResult {
+    let mut hash = blake2b::digest_256(data)?;
+    hash.reverse();
+    if hash.as_slice() <= target.as_slice() {
+        Ok(())
+    } else {
+        Err(PowError::CheckFailed)
+    }
+}
+
+/// Shared state of the workers searching for a stamp
+struct Search<'a> {
+    public_key: &'a PublicKey,
+    target: [u8; TARGET_SIZE],
+    cancel: &'a AtomicBool,
+    done: AtomicBool,
+    attempts: AtomicU64,
+    result: Mutex<Option<Result<ProofOfWork, PowError>>>,
+}
 
+impl Search<'_> {
+    fn should_stop(&self) -> bool {
+        self.done.load(Ordering::Relaxed) || self.cancel.load(Ordering::Relaxed)
+    }
+
+    fn finish(&self, result: Result<ProofOfWork, PowError>) {
+        let mut slot = self.result.lock().unwrap_or_else(|e| e.into_inner());
+        if slot.is_none() {
+            *slot = Some(result);
+        }
+        self.done.store(true, Ordering::Relaxed);
+    }
+
+    fn work(&self) {
+        let key = self.public_key.as_ref().as_ref();
+        let mut data = [key, &[0u8; POW_SIZE]].concat();
+        let mut nonce = Nonce::random();
+
+        while !self.should_stop() {
+            for _ in 0..ATTEMPTS_BATCH {
+                let stamp = nonce.get_bytes();
+                data[key.len()..].copy_from_slice(&stamp);
+                match check_target(&data, &self.target) {
+                    Ok(()) => return self.finish(Ok(ProofOfWork(stamp))),
+                    Err(PowError::CheckFailed) => nonce = nonce.increment(),
+                    Err(e) => return self.finish(Err(e)),
+                }
+            }
+            self.attempts.fetch_add(ATTEMPTS_BATCH, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct ProofOfWork([u8; POW_SIZE]);
+
+impl ProofOfWork {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoError> {
         if bytes.len() != POW_SIZE {
             return Err(CryptoError::InvalidKeySize {
                 expected: POW_SIZE,
@@ -38,15 +143,83 @@ impl FromHex for ProofOfWork {
         }
 
         let mut arr = [0u8; POW_SIZE];
-        arr.copy_from_slice(&bytes);
+        arr.copy_from_slice(bytes);
         Ok(ProofOfWork(arr))
     }
+
+    /// Searches a stamp for `public_key` reaching the `target` difficulty on every CPU core.
+    /// `progress` receives the number of attempts about once per second and setting
+    /// `cancel` stops the search with [`PowError::Cancelled`].
+    pub fn generate<F: Fn(u64)>(
+        public_key: &PublicKey,
+        target: f64,
+        cancel: &AtomicBool,
+        progress: F,
+    ) -> Result<Self, PowError> {
+        let search = Search {
+            public_key,
+            target: make_target(target),
+            cancel,
+            done: AtomicBool::new(false),
+            attempts: AtomicU64::new(0),
+            result: Mutex::new(None),
+        };
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| search.work());
+            }
+
+            let mut last_report = Instant::now();
+            while !search.should_stop() {
+                std::thread::sleep(POLL_INTERVAL);
+                if last_report.elapsed() >= PROGRESS_INTERVAL {
+                    progress(search.attempts.load(Ordering::Relaxed));
+                    last_report = Instant::now();
+                }
+            }
+        });
+
+        let result = search
+            .result
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+        result.unwrap_or(Err(PowError::Cancelled))
+    }
+
+    /// Checks this stamp against the public key it was generated for.
+    pub fn check(&self, public_key: &PublicKey, target: f64) -> PowResult {
+        let mut data = Vec::with_capacity(public_key.as_ref().as_ref().len() + POW_SIZE);
+        data.extend_from_slice(public_key.as_ref().as_ref());
+        data.extend_from_slice(&self.0);
+        check_proof_of_work(&data, target)
+    }
+}
+
+impl AsRef<[u8]> for ProofOfWork {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl FromHex for ProofOfWork {
+    type Error = CryptoError;
+
+    fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self, Self::Error> {
+        Self::from_bytes(&hex::decode(hex)?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn sample_public_key() -> PublicKey {
+        PublicKey::from_hex("17f7d11892274a7230d969aa1335d25e637f43087b76d0e24a1a8b7d03168f5c")
+            .unwrap()
+    }
+
     #[test]
     fn test_proof_of_work_from_hex() {
         let hex_string = "b6a4a80d765047918b037c85958c41096326a4b52ff0377e";
@@ -77,4 +250,50 @@ mod tests {
             _ => panic!("Unexpected error type"),
         }
     }
+
+    #[test]
+    fn test_make_target() {
+        assert_eq!(make_target(0.0), [0xff; TARGET_SIZE]);
+        assert_eq!(make_target(256.0), [0; TARGET_SIZE]);
+
+        let target = make_target(24.0);
+        assert_eq!(target[..3], [0, 0, 0]);
+        assert!(target[3..].iter().all(|b| *b == 0xff));
+
+        let target = make_target(24.5);
+        assert_eq!(target[..3], [0, 0, 0]);
+        assert_eq!(target[3], 0xb5);
+    }
+
+    #[test]
+    fn test_check_proof_of_work() {
+        let pow =
+            ProofOfWork::from_hex("b6a4a80d765047918b037c85958c41096326a4b52ff0377e").unwrap();
+        assert!(pow.check(&sample_public_key(), 0.0).is_ok());
+        assert!(pow.check(&sample_public_key(), 26.0).is_ok());
+        assert!(pow.check(&sample_public_key(), 30.0).is_err());
+    }
+
+    #[test]
+    fn test_check_proof_of_work_junk_stamp() {
+        let pow = ProofOfWork::from_bytes(&[0u8; POW_SIZE]).unwrap();
+        assert!(matches!(
+            pow.check(&sample_public_key(), 26.0),
+            Err(PowError::CheckFailed)
+        ));
+    }
+
+    #[test]
+    fn test_generate_proof_of_work() {
+        let cancel = AtomicBool::new(false);
+        let pow = ProofOfWork::generate(&sample_public_key(), 8.0, &cancel, |_| {}).unwrap();
+        assert!(pow.check(&sample_public_key(), 8.0).is_ok());
+    }
+
+    #[test]
+    fn test_generate_proof_of_work_cancelled() {
+        let cancel = AtomicBool::new(true);
+        let result = ProofOfWork::generate(&sample_public_key(), 256.0, &cancel, |_| {});
+        assert!(matches!(result, Err(PowError::Cancelled)));
+    }
 }
@@ -0,0 +1,250 @@
+use super::key::{wipe, CryptoKey, SecretKey, CRYPTO_KEY_SIZE};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::{pwhash::argon2id13, secretbox};
+use thiserror::Error;
+
+/// Key derivation function of sealed keys, the only one we write
+pub const KDF_ARGON2ID13: &str = "argon2id13";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SealError {
+    #[error("Unsupported key derivation function '{kdf}'")]
+    UnsupportedKdf { kdf: String },
+
+    #[error("Invalid sealed key field '{field}', reason: {reason}")]
+    InvalidField { field: &'static str, reason: String },
+
+    #[error("Key derivation from the passphrase failed")]
+    KeyDerivation,
+
+    #[error("Wrong passphrase or corrupted secret key")]
+    WrongPassphrase,
+}
+
+/// A secret key sealed with sodium secretbox, under a key derived from a
+/// passphrase with argon2id. Everything needed to open it again but the
+/// passphrase is kept along, hex encoded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SealedSecretKey {
+    kdf: String,
+    salt: String,
+    opslimit: usize,
+    memlimit: usize,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl SealedSecretKey {
+    /// Seals `secret_key` with the libsodium interactive argon2id limits.
+    pub fn seal(secret_key: &SecretKey, passphrase: &[u8]) -> Result<Self, SealError> {
+        Self::seal_with(
+            secret_key,
+            passphrase,
+            argon2id13::OPSLIMIT_INTERACTIVE,
+            argon2id13::MEMLIMIT_INTERACTIVE,
+        )
+    }
+
+    pub fn seal_with(
+        secret_key: &SecretKey,
+        passphrase: &[u8],
+        opslimit: argon2id13::OpsLimit,
+        memlimit: argon2id13::MemLimit,
+    ) -> Result<Self, SealError> {
+        check_limits(opslimit.0, memlimit.0)?;
+        let salt = argon2id13::gen_salt();
+        let key = derive_key(passphrase, &salt, opslimit, memlimit)?;
+        let nonce = secretbox::gen_nonce();
+        let ciphertext = secretbox::seal(&secret_key.as_ref().0, &nonce, &key);
+
+        Ok(Self {
+            kdf: KDF_ARGON2ID13.to_string(),
+            salt: hex::encode(salt.0),
+            opslimit: opslimit.0,
+            memlimit: memlimit.0,
+            nonce: hex::encode(nonce.0),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    pub fn open(&self, passphrase: &[u8]) -> Result<SecretKey, SealError> {
+        if self.kdf != KDF_ARGON2ID13 {
+            return Err(SealError::UnsupportedKdf {
+                kdf: self.kdf.clone(),
+            });
+        }
+        let salt = argon2id13::Salt::from_slice(&decode_field("salt", &self.salt)?).ok_or(
+            SealError::InvalidField {
+                field: "salt",
+                reason: format!("expected {} bytes", argon2id13::SALTBYTES),
+            },
+        )?;
+        let nonce = secretbox::Nonce::from_slice(&decode_field("nonce", &self.nonce)?).ok_or(
+            SealError::InvalidField {
+                field: "nonce",
+                reason: format!("expected {} bytes", secretbox::NONCEBYTES),
+            },
+        )?;
+        let ciphertext = decode_field("ciphertext", &self.ciphertext)?;
+        check_limits(self.opslimit, self.memlimit)?;
+
+        let key = derive_key(
+            passphrase,
+            &salt,
+            argon2id13::OpsLimit(self.opslimit),
+            argon2id13::MemLimit(self.memlimit),
+        )?;
+        let plaintext =
+            secretbox::open(&ciphertext, &nonce, &key).map_err(|()| SealError::WrongPassphrase)?;
+        let secret_key = SecretKey::from_bytes(&plaintext).map_err(|_| SealError::InvalidField {
+            field: "ciphertext",
+            reason: format!("expected a {} bytes secret key", CRYPTO_KEY_SIZE),
+        });
+        wipe(plaintext);
+        secret_key
+    }
+}
+
+/// Keeps the argon2id limits between the libsodium interactive and sensitive ones, a
+/// tampered file could otherwise make us allocate gigabytes or weaken the derivation
+fn check_limits(opslimit: usize, memlimit: usize) -> Result<(), SealError> {
+    let check = |field, value, min: usize, max: usize| {
+        if (min..=max).contains(&value) {
+            Ok(())
+        } else {
+            Err(SealError::InvalidField {
+                field,
+                reason: format!("{} is outside {}..={}", value, min, max),
+            })
+        }
+    };
+    check(
+        "opslimit",
+        opslimit,
+        argon2id13::OPSLIMIT_INTERACTIVE.0,
+        argon2id13::OPSLIMIT_SENSITIVE.0,
+    )?;
+    check(
+        "memlimit",
+        memlimit,
+        argon2id13::MEMLIMIT_INTERACTIVE.0,
+        argon2id13::MEMLIMIT_SENSITIVE.0,
+    )
+}
+
+fn derive_key(
+    passphrase: &[u8],
+    salt: &argon2id13::Salt,
+    opslimit: argon2id13::OpsLimit,
+    memlimit: argon2id13::MemLimit,
+) -> Result<secretbox::Key, SealError> {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    argon2id13::derive_key(&mut key.0, passphrase, salt, opslimit, memlimit)
+        .map_err(|()| SealError::KeyDerivation)?;
+    Ok(key)
+}
+
+fn decode_field(field: &'static str, value: &str) -> Result<Vec<u8>, SealError> {
+    hex::decode(value).map_err(|e| SealError::InvalidField {
+        field,
+        reason: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key::random_keypair;
+
+    fn seal(secret_key: &SecretKey, passphrase: &[u8]) -> SealedSecretKey {
+        SealedSecretKey::seal(secret_key, passphrase).unwrap()
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let (_, secret_key) = random_keypair();
+        let sealed = seal(&secret_key, b"correct horse");
+        assert_eq!(sealed.open(b"correct horse").unwrap(), secret_key);
+        assert!(!sealed
+            .ciphertext
+            .contains(&hex::encode(secret_key.as_ref().0)));
+    }
+
+    #[test]
+    fn test_open_wrong_passphrase() {
+        let (_, secret_key) = random_keypair();
+        let sealed = seal(&secret_key, b"correct horse");
+        assert_eq!(
+            sealed.open(b"battery staple"),
+            Err(SealError::WrongPassphrase)
+        );
+    }
+
+    #[test]
+    fn test_open_unsupported_kdf() {
+        let (_, secret_key) = random_keypair();
+        let mut sealed = seal(&secret_key, b"correct horse");
+        sealed.kdf = "scrypt".to_string();
+        assert!(matches!(
+            sealed.open(b"correct horse"),
+            Err(SealError::UnsupportedKdf { .. })
+        ));
+    }
+
+    fn assert_invalid_limits(opslimit: usize, memlimit: usize, field: &str) {
+        let (_, secret_key) = random_keypair();
+        let mut sealed = seal(&secret_key, b"correct horse");
+        sealed.opslimit = opslimit;
+        sealed.memlimit = memlimit;
+        assert!(matches!(
+            sealed.open(b"correct horse"),
+            Err(SealError::InvalidField { field: f, .. }) if f == field
+        ));
+    }
+
+    #[test]
+    fn test_open_opslimit_below_interactive() {
+        assert_invalid_limits(
+            argon2id13::OPSLIMIT_INTERACTIVE.0 - 1,
+            argon2id13::MEMLIMIT_INTERACTIVE.0,
+            "opslimit",
+        );
+    }
+
+    #[test]
+    fn test_open_opslimit_above_sensitive() {
+        assert_invalid_limits(
+            argon2id13::OPSLIMIT_SENSITIVE.0 + 1,
+            argon2id13::MEMLIMIT_INTERACTIVE.0,
+            "opslimit",
+        );
+    }
+
+    #[test]
+    fn test_open_memlimit_below_interactive() {
+        assert_invalid_limits(argon2id13::OPSLIMIT_INTERACTIVE.0, 8192, "memlimit");
+    }
+
+    #[test]
+    fn test_open_memlimit_above_sensitive() {
+        assert_invalid_limits(
+            argon2id13::OPSLIMIT_INTERACTIVE.0,
+            argon2id13::MEMLIMIT_SENSITIVE.0 + 1,
+            "memlimit",
+        );
+    }
+
+    #[test]
+    fn test_seal_with_weak_limits() {
+        let (_, secret_key) = random_keypair();
+        assert!(matches!(
+            SealedSecretKey::seal_with(
+                &secret_key,
+                b"correct horse",
+                argon2id13::OpsLimit(1),
+                argon2id13::MemLimit(8192),
+            ),
+            Err(SealError::InvalidField { .. })
+        ));
+    }
+}
@@ -0,0 +1,519 @@
+use crate::{
+    constants::BOOTSTRAP_DEFAULT_PORT,
+    crypto::{
+        identity::{Identity, IdentityError},
+        key::wipe,
+        peer_id::PeerId,
+    },
+    p2p::network::{NetworkConfig, NetworkConfigError},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+pub const IDENTITY_FILE: &str = "identity.json";
+pub const CONFIG_FILE: &str = "config.json";
+pub const PEERS_FILE: &str = "peers.json";
+
+#[derive(Error, Debug)]
+pub enum DataDirError {
+    #[error("Data directory {path} does not exist or is not a directory")]
+    NotADirectory { path: PathBuf },
+
+    #[error("Missing {path}, generate an identity with `identity generate {path}`")]
+    MissingIdentity { path: PathBuf },
+
+    #[error("I/O error reading {path}: {reason}")]
+    IoError { path: PathBuf, reason: io::Error },
+
+    #[error("Invalid identity in {path}, reason: {reason}")]
+    InvalidIdentity {
+        path: PathBuf,
+        reason: IdentityError,
+    },
+
+    #[error("Invalid JSON in {path}, reason: {reason}")]
+    InvalidJson {
+        path: PathBuf,
+        reason: serde_json::Error,
+    },
+
+    #[error("Unknown network '{name}' in {path}, expected mainnet, ghostnet, sandbox or a network object")]
+    UnknownNetwork { path: PathBuf, name: String },
+
+    #[error("Invalid network in {path}, reason: {reason}")]
+    InvalidNetwork {
+        path: PathBuf,
+        reason: NetworkConfigError,
+    },
+
+    #[error("Invalid p2p listen-addr '{addr}' in {path}")]
+    InvalidListenAddr { path: PathBuf, addr: String },
+
+    #[error("Invalid peer #{index} in {path}, reason: {reason}")]
+    InvalidKnownPeer {
+        path: PathBuf,
+        index: usize,
+        reason: String,
+    },
+
+    #[error("Identity proof-of-work in {identity} is below the expected-proof-of-work {expected} of {config}")]
+    InsufficientProofOfWork {
+        identity: PathBuf,
+        config: PathBuf,
+        expected: f64,
+    },
+}
+
+/// Node settings read from an octez `config.json`, unset values keep our defaults
+#[derive(Debug, Clone, Default)]
+pub struct NodeConfig {
+    pub network: Option<NetworkConfig>,
+    pub listen_addr: Option<SocketAddr>,
+    /// Replaces the network bootstrap peers when set
+    pub bootstrap_peers: Option<Vec<String>>,
+    pub expected_pow: Option<f64>,
+    pub max_connections: Option<usize>,
+    pub private_mode: bool,
+    pub disable_mempool: bool,
+}
+
+impl NodeConfig {
+    /// Network to join: `cli` when given on the command line, kept as is, otherwise
+    /// the `config.json` one (mainnet when unset) with its `bootstrap-peers`.
+    /// Those peers belong to the data directory network and never replace the
+    /// bootstrap peers of another one.
+    pub fn resolve_network(&self, cli: Option<NetworkConfig>) -> NetworkConfig {
+        if let Some(network) = cli {
+            return network;
+        }
+        let mut network = self.network.clone().unwrap_or_else(NetworkConfig::mainnet);
+        if let Some(bootstrap_peers) = self.bootstrap_peers.clone() {
+            network.default_bootstrap_peers = bootstrap_peers;
+        }
+        network
+    }
+}
+
+/// A peer octez met before, as stored in `peers.json`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownPeer {
+    pub peer_id: PeerId,
+    /// Last point the peer was reached at, if any
+    pub point: Option<SocketAddr>,
+}
+
+/// Everything loaded from an octez node data directory
+#[derive(Debug, Clone)]
+pub struct DataDir {
+    pub path: PathBuf,
+    pub identity: Identity,
+    pub config: NodeConfig,
+    pub known_peers: Vec<KnownPeer>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct ConfigJson {
+    network: Option<Value>,
+    #[serde(default)]
+    p2p: P2pJson,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct P2pJson {
+    listen_addr: Option<String>,
+    bootstrap_peers: Option<Vec<String>>,
+    expected_proof_of_work: Option<f64>,
+    #[serde(default)]
+    limits: LimitsJson,
+    #[serde(default)]
+    private_mode: bool,
+    #[serde(default, alias = "disable_mempool")]
+    disable_mempool: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct LimitsJson {
+    max_connections: Option<usize>,
+}
+
+/// Fields of a `peers.json` entry we use, the other ones are ignored
+#[derive(Deserialize)]
+struct KnownPeerJson {
+    peer_id: String,
+    last_established_connection: Option<(Value, Value)>,
+    last_seen: Option<(Value, Value)>,
+    last_rejected_connection: Option<(Value, Value)>,
+    last_failed_connection: Option<(Value, Value)>,
+}
+
+impl DataDir {
+    /// Loads `identity.json`, which is required, then `config.json` and `peers.json` when present.
+    pub fn load(path: &Path) -> Result<Self, DataDirError> {
+        if !path.is_dir() {
+            return Err(DataDirError::NotADirectory {
+                path: path.to_path_buf(),
+            });
+        }
+
+        let identity_path = path.join(IDENTITY_FILE);
+        let identity = load_identity(&identity_path)?;
+
+        let config_path = path.join(CONFIG_FILE);
+        let config = match read_optional(&config_path)? {
+            Some(json) => parse_config(&config_path, &json)?,
+            None => NodeConfig::default(),
+        };
+        let expected_pow = config.expected_pow.unwrap_or(0.0);
+        identity.validate(expected_pow).map_err(|e| match e {
+            IdentityError::InsufficientProofOfWork { expected } => {
+                DataDirError::InsufficientProofOfWork {
+                    identity: identity_path.clone(),
+                    config: config_path.clone(),
+                    expected,
+                }
+            }
+            e => DataDirError::InvalidIdentity {
+                path: identity_path.clone(),
+                reason: e,
+            },
+        })?;
+
+        let peers_path = path.join(PEERS_FILE);
+        let known_peers = match read_optional(&peers_path)? {
+            Some(json) => parse_known_peers(&peers_path, &json)?
+                .into_iter()
+                .filter(|p| p.peer_id != identity.peer_id)
+                .collect(),
+            None => vec![],
+        };
+
+        Ok(DataDir {
+            path: path.to_path_buf(),
+            identity,
+            config,
+            known_peers,
+        })
+    }
+
+    /// Points of the known peers, to dial after the bootstrap ones
+    pub fn known_points(&self) -> Vec<SocketAddr> {
+        self.known_peers.iter().filter_map(|p| p.point).collect()
+    }
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>, DataDirError> {
+    match std::fs::read_to_string(path) {
+        Ok(json) => Ok(Some(json)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(DataDirError::IoError {
+            path: path.to_path_buf(),
+            reason: e,
+        }),
+    }
+}
+
+fn load_identity(path: &Path) -> Result<Identity, DataDirError> {
+    let json = read_optional(path)?.ok_or_else(|| DataDirError::MissingIdentity {
+        path: path.to_path_buf(),
+    })?;
+    let identity =
+        Identity::from_json_or_prompt(&json).map_err(|e| DataDirError::InvalidIdentity {
+            path: path.to_path_buf(),
+            reason: e,
+        });
+    wipe(json);
+    identity
+}
+
+fn parse_config(path: &Path, json: &str) -> Result<NodeConfig, DataDirError> {
+    let config: ConfigJson = serde_json::from_str(json).map_err(|e| DataDirError::InvalidJson {
+        path: path.to_path_buf(),
+        reason: e,
+    })?;
+
+    let network = match config.network {
+        None => None,
+        Some(Value::String(name)) => {
+            Some(
+                NetworkConfig::from_alias(&name).ok_or_else(|| DataDirError::UnknownNetwork {
+                    path: path.to_path_buf(),
+                    name,
+                })?,
+            )
+        }
+        Some(value) => Some(NetworkConfig::from_json_value(value).map_err(|e| {
+            DataDirError::InvalidNetwork {
+                path: path.to_path_buf(),
+                reason: e,
+            }
+        })?),
+    };
+
+    let listen_addr = config
+        .p2p
+        .listen_addr
+        .map(|addr| {
+            parse_listen_addr(&addr).ok_or_else(|| DataDirError::InvalidListenAddr {
+                path: path.to_path_buf(),
+                addr,
+            })
+        })
+        .transpose()?;
+
+    Ok(NodeConfig {
+        network,
+        listen_addr,
+        bootstrap_peers: config.p2p.bootstrap_peers,
+        expected_pow: config.p2p.expected_proof_of_work,
+        max_connections: config.p2p.limits.max_connections,
+        private_mode: config.p2p.private_mode,
+        disable_mempool: config.p2p.disable_mempool,
+    })
+}
+
+/// octez accepts `[::]:9732`, `0.0.0.0:9732` or only a port as `:9732`
+fn parse_listen_addr(addr: &str) -> Option<SocketAddr> {
+    match addr.strip_prefix(':') {
+        Some(port) => Some(SocketAddr::new(IpAddr::from([0u16; 8]), port.parse().ok()?)),
+        None => addr.parse().ok(),
+    }
+}
+
+fn parse_known_peers(path: &Path, json: &str) -> Result<Vec<KnownPeer>, DataDirError> {
+    let peers: Vec<Value> = serde_json::from_str(json).map_err(|e| DataDirError::InvalidJson {
+        path: path.to_path_buf(),
+        reason: e,
+    })?;
+
+    peers
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let invalid = |reason: String| DataDirError::InvalidKnownPeer {
+                path: path.to_path_buf(),
+                index,
+                reason,
+            };
+            let peer: KnownPeerJson =
+                serde_json::from_value(value).map_err(|e| invalid(e.to_string()))?;
+            let peer_id = peer
+                .peer_id
+                .parse()
+                .map_err(|e| invalid(format!("invalid peer_id '{}': {}", peer.peer_id, e)))?;
+            let point = [
+                peer.last_established_connection,
+                peer.last_seen,
+                peer.last_rejected_connection,
+                peer.last_failed_connection,
+            ]
+            .into_iter()
+            .flatten()
+            .find_map(|(point, _)| parse_point(&point));
+            Ok(KnownPeer { peer_id, point })
+        })
+        .collect()
+}
+
+/// Points are `{ "addr": "::ffff:1.2.3.4", "port": 9732 }` objects, `ip:port` strings are accepted too
+fn parse_point(point: &Value) -> Option<SocketAddr> {
+    match point {
+        Value::String(point) => point.parse().ok(),
+        Value::Object(point) => {
+            let addr: IpAddr = point.get("addr")?.as_str()?.parse().ok()?;
+            let port = match point.get("port") {
+                Some(port) => u16::try_from(port.as_u64()?).ok()?,
+                None => BOOTSTRAP_DEFAULT_PORT,
+            };
+            Some(SocketAddr::new(addr.to_canonical(), port))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DEFAUL_IDENTITY_JSON;
+
+    fn data_dir_with(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    const CONFIG_JSON: &str = r#"{
+  "data-dir": "/var/lib/octez",
+  "network": "ghostnet",
+  "p2p": {
+    "listen-addr": "[::]:19732",
+    "bootstrap-peers": [ "127.0.0.1:9733" ],
+    "expected-proof-of-work": 24,
+    "limits": { "min-connections": 5, "max-connections": 20 },
+    "private-mode": true
+  }
+}"#;
+
+    const PEERS_JSON: &str = r#"[
+  { "peer_id": "idqSUKFJXszraVQgd8bPesWkT5Pf7o", "score": 0, "trusted": false,
+    "last_established_connection": [ { "addr": "::ffff:1.2.3.4", "port": 9732 }, "2024-01-01T00:00:00Z" ] },
+  { "peer_id": "idsfYM6UbG2nhNS1dqhsJEchaDhmd9", "score": 0, "trusted": false,
+    "last_seen": [ { "addr": "::ffff:5.6.7.8", "port": 9732 }, "2024-01-01T00:00:00Z" ] },
+  { "peer_id": "idqTHN9NvaN1XLnWVPoLKdAQ6cyJLg", "score": 0, "trusted": false }
+]"#;
+
+    #[test]
+    fn test_load_data_dir() {
+        let dir = data_dir_with(&[
+            (IDENTITY_FILE, DEFAUL_IDENTITY_JSON),
+            (CONFIG_FILE, CONFIG_JSON),
+            (PEERS_FILE, PEERS_JSON),
+        ]);
+        let data_dir = DataDir::load(dir.path()).unwrap();
+
+        assert_eq!(
+            data_dir.identity.peer_id.to_string(),
+            "idsfYM6UbG2nhNS1dqhsJEchaDhmd9"
+        );
+        let config = &data_dir.config;
+        assert_eq!(config.network, Some(NetworkConfig::ghostnet()));
+        assert_eq!(config.listen_addr, Some("[::]:19732".parse().unwrap()));
+        assert_eq!(
+            config.bootstrap_peers,
+            Some(vec!["127.0.0.1:9733".to_string()])
+        );
+        assert_eq!(config.expected_pow, Some(24.0));
+        assert_eq!(config.max_connections, Some(20));
+        assert!(config.private_mode);
+        assert!(!config.disable_mempool);
+
+        // Our own entry is skipped
+        assert_eq!(data_dir.known_peers.len(), 2);
+        assert_eq!(
+            data_dir.known_points(),
+            vec!["1.2.3.4:9732".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_resolve_network() {
+        let dir = data_dir_with(&[
+            (IDENTITY_FILE, DEFAUL_IDENTITY_JSON),
+            (CONFIG_FILE, CONFIG_JSON),
+        ]);
+        let config = DataDir::load(dir.path()).unwrap().config;
+
+        let network = config.resolve_network(None);
+        assert_eq!(network.chain_name, NetworkConfig::ghostnet().chain_name);
+        assert_eq!(network.default_bootstrap_peers, vec!["127.0.0.1:9733"]);
+
+        // A network from the command line keeps its own bootstrap peers
+        let network = config.resolve_network(Some(NetworkConfig::mainnet()));
+        assert_eq!(network, NetworkConfig::mainnet());
+
+        let network = NodeConfig::default().resolve_network(None);
+        assert_eq!(network, NetworkConfig::mainnet());
+    }
+
+    #[test]
+    fn test_load_data_dir_identity_only() {
+        let dir = data_dir_with(&[(IDENTITY_FILE, DEFAUL_IDENTITY_JSON)]);
+        let data_dir = DataDir::load(dir.path()).unwrap();
+        assert!(data_dir.config.network.is_none());
+        assert!(data_dir.known_peers.is_empty());
+    }
+
+    #[test]
+    fn test_load_data_dir_missing_identity() {
+        let dir = data_dir_with(&[(CONFIG_FILE, CONFIG_JSON)]);
+        assert!(matches!(
+            DataDir::load(dir.path()),
+            Err(DataDirError::MissingIdentity { path }) if path.ends_with(IDENTITY_FILE)
+        ));
+    }
+
+    #[test]
+    fn test_load_data_dir_not_a_directory() {
+        assert!(matches!(
+            DataDir::load(Path::new("/nonexistent/octez")),
+            Err(DataDirError::NotADirectory { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_data_dir_unknown_network() {
+        let dir = data_dir_with(&[
+            (IDENTITY_FILE, DEFAUL_IDENTITY_JSON),
+            (CONFIG_FILE, r#"{ "network": "nairobinet" }"#),
+        ]);
+        assert!(matches!(
+            DataDir::load(dir.path()),
+            Err(DataDirError::UnknownNetwork { name, .. }) if name == "nairobinet"
+        ));
+    }
+
+    #[test]
+    fn test_load_data_dir_invalid_listen_addr() {
+        let dir = data_dir_with(&[
+            (IDENTITY_FILE, DEFAUL_IDENTITY_JSON),
+            (CONFIG_FILE, r#"{ "p2p": { "listen-addr": "localhost" } }"#),
+        ]);
+        assert!(matches!(
+            DataDir::load(dir.path()),
+            Err(DataDirError::InvalidListenAddr { addr, .. }) if addr == "localhost"
+        ));
+    }
+
+    #[test]
+    fn test_load_data_dir_insufficient_pow() {
+        let dir = data_dir_with(&[
+            (IDENTITY_FILE, DEFAUL_IDENTITY_JSON),
+            (
+                CONFIG_FILE,
+                r#"{ "p2p": { "expected-proof-of-work": 30 } }"#,
+            ),
+        ]);
+        assert!(matches!(
+            DataDir::load(dir.path()),
+            Err(DataDirError::InsufficientProofOfWork { expected, .. }) if expected == 30.0
+        ));
+    }
+
+    #[test]
+    fn test_load_data_dir_invalid_known_peer() {
+        let dir = data_dir_with(&[
+            (IDENTITY_FILE, DEFAUL_IDENTITY_JSON),
+            (
+                PEERS_FILE,
+                r#"[ { "peer_id": "idsfYM6UbG2nhNS1dqhsJEchaDhmd9" }, { "peer_id": "nope" } ]"#,
+            ),
+        ]);
+        assert!(matches!(
+            DataDir::load(dir.path()),
+            Err(DataDirError::InvalidKnownPeer { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            parse_listen_addr(":9732"),
+            Some("[::]:9732".parse().unwrap())
+        );
+        assert_eq!(
+            parse_listen_addr("0.0.0.0:9732"),
+            Some("0.0.0.0:9732".parse().unwrap())
+        );
+        assert_eq!(parse_listen_addr("9732"), None);
+    }
+}
@@ -1,17 +1,35 @@
 pub mod cli;
 pub mod constants;
 pub mod crypto;
+pub mod data_dir;
 pub mod msgs;
 pub mod p2p;
+pub mod passphrase;
 
 use clap::Parser;
-use std::{net::SocketAddr, str::FromStr};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use crate::{
-    cli::Cli,
-    constants::{BOOTSTRAP_DEFAULT_PORT, BOOTSTRAP_PEERS, DEFAUL_IDENTITY_JSON},
-    crypto::identity::Identity,
-    p2p::{dns, peer::Peer},
+    cli::{Cli, Command, IdentityCommand, Network},
+    constants::{
+        DEFAULT_LISTEN_ADDR, DEFAULT_MAX_CONNECTIONS, DEFAUL_IDENTITY_JSON, MAX_HANDSHAKE_ATTEMPTS,
+    },
+    crypto::{identity::Identity, key::wipe},
+    data_dir::DataDir,
+    p2p::{
+        config::PeerConfig,
+        listener,
+        network::NetworkConfig,
+        peer::{Peer, PeerError},
+    },
 };
 
 #[tokio::main]
@@ -19,41 +37,276 @@ async fn main() {
     println!("Starting... 🚀");
     let args = Cli::parse();
 
+    if let Some(command) = args.command {
+        return run_command(command).await;
+    }
+
+    let data_dir = args.data_dir.map(|path| {
+        println!("Reading data directory {}... 📂", path.display());
+        DataDir::load(&path)
+            .unwrap_or_else(|e| panic!("Failed to load data directory, Error: {}", e))
+    });
+    let node_config = data_dir
+        .as_ref()
+        .map(|d| d.config.clone())
+        .unwrap_or_default();
+
+    println!("Getting identity... 🪪");
+    let identity = if let Some(data_dir) = &data_dir {
+        data_dir.identity.clone()
+    } else if let Some(identity_path) = args.identity_path {
+        Identity::from_json_file(identity_path).expect("Failed to get identity")
+    } else {
+        Identity::from_json(DEFAUL_IDENTITY_JSON).expect("Failed to get identity")
+    };
+
+    let chain_name_only = args.chain_name.is_some();
+    let cli_network = match (args.network_file, args.chain_name, args.network) {
+        (Some(network_file), _, _) => Some(
+            NetworkConfig::from_json_file(&network_file)
+                .unwrap_or_else(|e| panic!("Failed to load network, Error: {}", e)),
+        ),
+        (None, Some(chain_name), _) => {
+            Some(NetworkConfig::sandbox().with_chain_name(chain_name.to_uppercase()))
+        }
+        (None, None, Some(Network::Mainnet)) => Some(NetworkConfig::mainnet()),
+        (None, None, Some(Network::Ghostnet)) => Some(NetworkConfig::ghostnet()),
+        (None, None, Some(Network::Sandbox)) => Some(NetworkConfig::sandbox()),
+        (None, None, None) => None,
+    };
+    let network = node_config.resolve_network(cli_network);
+    println!(
+        "Using network {} ({})... 🌐",
+        network.chain_name,
+        network
+            .chain_id()
+            .map_or_else(|e| e.to_string(), |id| id.to_string())
+    );
+    let mut config = network.peer_config().with_metadata(
+        args.disable_mempool || node_config.disable_mempool,
+        args.private_node || node_config.private_mode,
+    );
+    if chain_name_only {
+        // The genesis of a chain known by its name only is a guess, so is its chain id
+        config.chain_id = None;
+    }
+    if let Some(listen_addr) = node_config.listen_addr {
+        config = config.with_listening_port(listen_addr.port());
+    }
+    if let Some(expected_pow) = node_config.expected_pow {
+        config = config.with_expected_pow(expected_pow);
+    }
+    if let Some(expected_pow) = args.expected_pow {
+        config = config.with_expected_pow(expected_pow);
+    }
+    if let Some(versions) = args.distributed_db_versions {
+        config = config.with_distributed_db_versions(versions);
+    }
+    if let Some(versions) = args.p2p_versions {
+        config = config.with_p2p_versions(versions);
+    }
+    if let Some(timeout) = args.connect_timeout {
+        config = config.with_connect_timeout(timeout);
+    }
+    if let Some(timeout) = args.handshake_timeout {
+        config = config.with_handshake_timeout(timeout);
+    }
+
+    if let Some(listen) = args.listen {
+        let listen_addr = match listen {
+            Some(listen) => SocketAddr::from_str(&listen).expect("Failed to parse listen address"),
+            None => node_config.listen_addr.unwrap_or(DEFAULT_LISTEN_ADDR),
+        };
+        let max_connections = args
+            .max_connections
+            .or(node_config.max_connections)
+            .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        listener::listen(listen_addr, identity, config, max_connections)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to listen for peers, Error: {}", e));
+        return;
+    }
+
     println!("Resolving peer address... 🧭");
     let peer_addr = if let Some(peer) = args.peer {
         SocketAddr::from_str(&peer).expect("Failed to parse peer address")
     } else {
         println!("Looking for active nodes... 🔎");
-        let boostrap_peers = dns::lookup_active_nodes(BOOTSTRAP_PEERS, BOOTSTRAP_DEFAULT_PORT);
+        let boostrap_peers = network.bootstrap_addrs();
+        if boostrap_peers.is_empty() {
+            panic!(
+                "No bootstrap peer for {}, give a peer address",
+                network.chain_name
+            );
+        }
         let rand = rand::random::<usize>() % boostrap_peers.len();
         boostrap_peers[rand]
     };
 
-    println!("Getting identity... 🪪");
-    let identity = if let Some(identity_path) = args.identity_path {
-        Identity::from_json_file(identity_path).expect("Failed to get identity")
-    } else {
-        Identity::from_json(DEFAUL_IDENTITY_JSON).expect("Failed to get identity")
+    // A node refusing us may suggest other points, try them before giving up,
+    // then the peers known from the data directory
+    let mut candidates = VecDeque::from([peer_addr]);
+    if let Some(data_dir) = &data_dir {
+        candidates.extend(
+            data_dir
+                .known_points()
+                .into_iter()
+                .filter(|p| *p != peer_addr),
+        );
+    }
+    let mut attempts = 0;
+    let (mut peer, peer_addr) = loop {
+        let Some(peer_addr) = candidates.pop_front() else {
+            panic!("Failed to handshake with peer, no candidate left");
+        };
+        attempts += 1;
+        match connect_and_handshake(peer_addr, identity.clone(), config.clone()).await {
+            Ok(peer) => break (peer, peer_addr),
+            Err(PeerError::Nack {
+                motive,
+                potential_peers,
+            }) if attempts < MAX_HANDSHAKE_ATTEMPTS => {
+                println!(
+                    "Peer {} refused us ({}), it suggested {} other peers... 🔀",
+                    peer_addr,
+                    motive,
+                    potential_peers.len()
+                );
+                candidates.extend(potential_peers);
+            }
+            Err(e) if attempts < MAX_HANDSHAKE_ATTEMPTS && !candidates.is_empty() => {
+                println!("Failed to handshake with peer {}, Error: {}", peer_addr, e);
+            }
+            Err(e) => panic!("Failed to handshake with peer, Error: {}", e),
+        }
     };
 
-    println!("Connecting to peer {}... 🛜", peer_addr);
-    let chain_name = args
-        .chain_name
-        .unwrap_or("TEZOS_MAINNET".to_string())
-        .to_uppercase();
-    let mut peer = Peer::connect(peer_addr, identity, chain_name)
-        .await
-        .unwrap_or_else(|e| panic!("Failed to connect to peer, Error: {}", e));
-
-    println!("Handshaking with peer {}... 🤝", peer_addr);
-    peer.handshake()
-        .await
-        .unwrap_or_else(|e| panic!("Failed to handshake with peer, Error: {}", e));
-
-    println!("Done, Handshake completed! 🎉");
+    match peer.info() {
+        Some(info) => {
+            println!("Done, Handshake completed with {}! 🎉", info.peer_id);
+            println!("Remote version: {}", info.version);
+            println!(
+                "Remote metadata: disable_mempool={}, private_node={}",
+                info.metadata.disable_mempool, info.metadata.private_node
+            );
+        }
+        None => println!("Done, Handshake completed! 🎉"),
+    }
 
     peer.desconnect()
         .await
         .unwrap_or_else(|e| panic!("Failed to disconnect from peer, Error: {}", e));
     println!("Disconnected from peer {}... 👋", peer_addr);
 }
+
+async fn connect_and_handshake(
+    peer_addr: SocketAddr,
+    identity: Identity,
+    config: PeerConfig,
+) -> Result<Peer, PeerError> {
+    println!("Connecting to peer {}... 🛜", peer_addr);
+    let mut peer = Peer::connect(peer_addr, identity, config).await?;
+
+    println!("Handshaking with peer {}... 🤝", peer_addr);
+    peer.handshake().await?;
+    Ok(peer)
+}
+
+async fn run_command(command: Command) {
+    match command {
+        Command::Identity(IdentityCommand::Generate {
+            output,
+            difficulty,
+            force,
+        }) => {
+            if !force && output.exists() {
+                panic!(
+                    "Identity file {} already exists, use --force to replace it",
+                    output.display()
+                );
+            }
+            println!("Generating identity with difficulty {}... ⛏️", difficulty);
+            let cancel = Arc::new(AtomicBool::new(false));
+            tokio::spawn({
+                let cancel = cancel.clone();
+                async move {
+                    if tokio::signal::ctrl_c().await.is_ok() {
+                        cancel.store(true, Ordering::Relaxed);
+                    }
+                }
+            });
+
+            let identity = tokio::task::spawn_blocking(move || {
+                Identity::generate_with(difficulty, &cancel, |attempts| {
+                    println!("Searching proof-of-work, {} attempts so far...", attempts)
+                })
+            })
+            .await
+            .expect("Identity generation panicked")
+            .unwrap_or_else(|e| panic!("Failed to generate identity, Error: {}", e));
+
+            identity
+                .to_json_file(&output, force)
+                .unwrap_or_else(|e| panic!("Failed to write identity, Error: {}", e));
+            println!(
+                "Identity {} written to {} 🪪",
+                identity.peer_id,
+                output.display()
+            );
+        }
+        Command::Identity(IdentityCommand::Check { path, expected_pow }) => {
+            println!("Checking identity {}... 🔍", path.display());
+            let result = Identity::from_json_file(path.clone())
+                .and_then(|identity| identity.validate(expected_pow).map(|()| identity));
+            match result {
+                Ok(identity) => println!(
+                    "Identity {} is valid for proof-of-work {} ✅",
+                    identity.peer_id, expected_pow
+                ),
+                Err(e) => {
+                    println!("Identity {} is invalid, Error: {} ❌", path.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Identity(IdentityCommand::Encrypt {
+            path,
+            output,
+            force,
+        }) => {
+            let json = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Failed to read identity, Error: {}", e));
+            if Identity::is_encrypted_json(&json) {
+                panic!("Identity {} is already encrypted", path.display());
+            }
+            let identity = Identity::from_json(&json);
+            wipe(json);
+            let identity =
+                identity.unwrap_or_else(|e| panic!("Failed to get identity, Error: {}", e));
+            let passphrase = passphrase::read_new_passphrase("New identity passphrase: ")
+                .unwrap_or_else(|e| panic!("Failed to read passphrase, Error: {}", e));
+
+            println!("Encrypting identity {}... 🔒", identity.peer_id);
+            let (output, force) = output.map_or((path, true), |output| (output, force));
+            let written = identity.to_encrypted_json_file(&output, &passphrase, force);
+            wipe(passphrase);
+            written.unwrap_or_else(|e| panic!("Failed to write identity, Error: {}", e));
+            println!("Encrypted identity written to {} 🪪", output.display());
+        }
+        Command::Identity(IdentityCommand::Decrypt {
+            path,
+            output,
+            force,
+        }) => {
+            let identity = Identity::from_json_file(path.clone())
+                .unwrap_or_else(|e| panic!("Failed to get identity, Error: {}", e));
+
+            println!("Decrypting identity {}... 🔓", identity.peer_id);
+            let (output, force) = output.map_or((path, true), |output| (output, force));
+            identity
+                .to_json_file(&output, force)
+                .unwrap_or_else(|e| panic!("Failed to write identity, Error: {}", e));
+            println!("Plain identity written to {} 🪪", output.display());
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use super::point::PointList;
 use speedy::{Readable, Writable};
+use std::fmt;
 
-#[derive(Debug, PartialEq, Readable, Writable)]
+#[derive(Debug, Clone, PartialEq, Readable, Writable)]
 #[speedy(tag_type = u8)]
 pub enum AckStatus {
     #[speedy(tag = 0x00)]
@@ -8,5 +10,93 @@ pub enum AckStatus {
     #[speedy(tag = 0xFF)]
     NackV1,
     #[speedy(tag = 0x01)]
-    NackV2,
+    NackV2(NackInfo),
+}
+
+/// Why a node refused the connection and which points we could try instead
+#[derive(Debug, Clone, PartialEq, Readable, Writable)]
+pub struct NackInfo {
+    pub motive: NackMotive,
+    pub potential_peers_to_connect: PointList,
+}
+
+impl NackInfo {
+    pub fn new(motive: NackMotive, potential_peers_to_connect: PointList) -> Self {
+        Self {
+            motive,
+            potential_peers_to_connect,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Readable, Writable)]
+#[speedy(tag_type = u16)]
+pub enum NackMotive {
+    #[speedy(tag = 0)]
+    NoMotive,
+    #[speedy(tag = 1)]
+    TooManyConnections,
+    #[speedy(tag = 2)]
+    UnknownChainName,
+    #[speedy(tag = 3)]
+    DeprecatedP2pVersion,
+    #[speedy(tag = 4)]
+    DeprecatedDistributedDbVersion,
+    #[speedy(tag = 5)]
+    AlreadyConnected,
+}
+
+impl fmt::Display for NackMotive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let motive = match self {
+            NackMotive::NoMotive => "no motive",
+            NackMotive::TooManyConnections => "too many connections",
+            NackMotive::UnknownChainName => "unknown chain name",
+            NackMotive::DeprecatedP2pVersion => "deprecated p2p version",
+            NackMotive::DeprecatedDistributedDbVersion => "deprecated distributed_db version",
+            NackMotive::AlreadyConnected => "already connected",
+        };
+        f.write_str(motive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use speedy::Endianness;
+
+    #[test]
+    fn test_decode_ack() {
+        let ack = AckStatus::read_from_buffer_with_ctx(Endianness::BigEndian, &[0x00]).unwrap();
+        assert_eq!(ack, AckStatus::Ack);
+        let nack = AckStatus::read_from_buffer_with_ctx(Endianness::BigEndian, &[0xFF]).unwrap();
+        assert_eq!(nack, AckStatus::NackV1);
+    }
+
+    #[test]
+    fn test_decode_nack_v2() {
+        let mut bytes = vec![
+            0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x0c,
+        ];
+        bytes.extend_from_slice(b"1.2.3.4:9732");
+        let nack = AckStatus::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(
+            nack,
+            AckStatus::NackV2(NackInfo::new(
+                NackMotive::TooManyConnections,
+                PointList(vec!["1.2.3.4:9732".to_string()])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_nack_v2_round_trip() {
+        let nack = AckStatus::NackV2(NackInfo::new(
+            NackMotive::UnknownChainName,
+            PointList(vec!["[::1]:9732".to_string()]),
+        ));
+        let bytes = nack.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        let decoded = AckStatus::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, nack);
+    }
 }
@@ -0,0 +1,151 @@
+use super::dynamic::DynamicList;
+use crate::crypto::{
+    blake2b::{self, Blake2bError},
+    hash::{BlockHash, ContextHash, OperationListListHash},
+};
+use speedy::{Endianness, Readable, Writable};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BlockHeaderError {
+    #[error("Block header encoding failed: {0}")]
+    Encoding(speedy::Error),
+    #[error("Block header hashing failed: {0}")]
+    Blake2b(Blake2bError),
+    #[error("Block header hash {found} does not match the requested {expected}")]
+    HashMismatch {
+        expected: BlockHash,
+        found: BlockHash,
+    },
+}
+
+/// Tezos block header: the shell header every node understands,
+/// followed by protocol specific data kept opaque.
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct BlockHeader {
+    pub level: i32,
+    pub proto: u8,
+    pub predecessor: BlockHash,
+    /// Seconds since the Unix epoch
+    pub timestamp: i64,
+    pub validation_pass: u8,
+    pub operations_hash: OperationListListHash,
+    pub fitness: DynamicList<Vec<u8>>,
+    pub context: ContextHash,
+    /// Protocol data, it fills the rest of the header
+    #[speedy(length = ..)]
+    pub protocol_data: Vec<u8>,
+}
+
+impl BlockHeader {
+    /// The block hash is the blake2b-256 digest of the encoded header
+    pub fn hash(&self) -> Result<BlockHash, BlockHeaderError> {
+        let bytes = self
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .map_err(BlockHeaderError::Encoding)?;
+        let digest = blake2b::digest_256(&bytes).map_err(BlockHeaderError::Blake2b)?;
+        let mut hash = [0u8; BlockHash::SIZE];
+        hash.copy_from_slice(&digest);
+        Ok(BlockHash::from(hash))
+    }
+
+    /// Checks a received header is the block we asked for
+    pub fn verify(&self, expected: &BlockHash) -> Result<(), BlockHeaderError> {
+        let found = self.hash()?;
+        if &found != expected {
+            return Err(BlockHeaderError::HashMismatch {
+                expected: *expected,
+                found,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn sample_block_header() -> BlockHeader {
+    BlockHeader {
+        level: 5_000_000,
+        proto: 19,
+        predecessor: [1; 32].into(),
+        timestamp: 1_700_000_000,
+        validation_pass: 4,
+        operations_hash: [2; 32].into(),
+        fitness: DynamicList(vec![vec![0x02], vec![0x00, 0x4c, 0x4b, 0x40]]),
+        context: [3; 32].into(),
+        protocol_data: vec![0xde, 0xad, 0xbe, 0xef],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_header_encoding() {
+        let header = sample_block_header();
+        let bytes = header.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+
+        let mut expected = vec![0x00, 0x4c, 0x4b, 0x40, 19];
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(&1_700_000_000i64.to_be_bytes());
+        expected.push(4);
+        expected.extend_from_slice(&[2; 32]);
+        expected.extend_from_slice(&[0, 0, 0, 13, 0, 0, 0, 1, 0x02, 0, 0, 0, 4]);
+        expected.extend_from_slice(&[0x00, 0x4c, 0x4b, 0x40]);
+        expected.extend_from_slice(&[3; 32]);
+        expected.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(bytes, expected);
+
+        let decoded =
+            BlockHeader::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, header);
+    }
+
+    /// Level 1 header on top of the mainnet genesis, with the genesis context and the
+    /// empty operations hash. Its hash was computed outside this crate, blake2b-256 of
+    /// these bytes then base58check with the block hash prefix.
+    const KNOWN_HEADER_HEX: &str = "00000001008fcf233671b6a04fcf679d2a381c2544ea6c1ea29ba6157776ed8424c7ccd00b000000005b37c06d000e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a80000001100000001000000000800000000000000013fdf8e76abec28f333dca1964e86404e455f5fc7838012558d9e43e23937ea88000000000000000000";
+    const KNOWN_HEADER_HASH: &str = "BLUgMqEHmJQZxnq7Y4DZT1ep88BDJkFexoMKZFGA2NwFdYBg3kT";
+
+    #[test]
+    fn test_block_header_hash() {
+        let bytes = hex::decode(KNOWN_HEADER_HEX).unwrap();
+        let header = BlockHeader::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(header.level, 1);
+        assert_eq!(
+            header.predecessor.to_base58check(),
+            "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2"
+        );
+        assert_eq!(header.timestamp, 1_530_380_397);
+        assert_eq!(
+            header.operations_hash.to_base58check(),
+            "LLoZS2LW3rEi7KYU4ouBQtorua37aWWCtpDmv1n2x3xoKi6sVXLWp"
+        );
+        assert_eq!(
+            header.fitness,
+            DynamicList(vec![vec![0x00], vec![0, 0, 0, 0, 0, 0, 0, 1]])
+        );
+        assert_eq!(
+            header.context.to_base58check(),
+            "CoV8SQumiVU9saiu3FVNeDNewJaJH8yWdsGF3WLdsRr2P9S7MzCj"
+        );
+        assert_eq!(header.protocol_data, vec![0; 9]);
+        assert_eq!(header.hash().unwrap().to_base58check(), KNOWN_HEADER_HASH);
+        assert!(header.verify(&KNOWN_HEADER_HASH.parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_block_header_verify() {
+        let header = sample_block_header();
+        let hash = header.hash().unwrap();
+        assert!(header.verify(&hash).is_ok());
+
+        let mut tampered = header.clone();
+        tampered.level += 1;
+        assert!(matches!(
+            tampered.verify(&hash),
+            Err(BlockHeaderError::HashMismatch { .. })
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 use speedy::{Readable, Writable};
+use std::fmt;
 
 pub const CRYPTO_KEY_LENGTH: usize = 32;
 pub const POW_LENGTH: usize = 24;
@@ -14,8 +15,11 @@ pub struct ConnectionMessage {
     pub proof_of_work_stamp: Vec<u8>,
     #[speedy(length  = NONCE_LENGTH)]
     pub message_nonce: Vec<u8>,
-    pub version_length: u16,
-    pub version: NetworkVersion,
+    /// Supported versions, they fill the rest of the message without a length prefix.
+    /// octez encodes a single `Network_version` there, so a one element list is
+    /// exactly its encoding and is all we announce.
+    #[speedy(length = ..)]
+    pub versions: Vec<NetworkVersion>,
 }
 
 impl speedy::Context for ConnectionMessage {
@@ -31,22 +35,21 @@ impl ConnectionMessage {
         public_key: Vec<u8>,
         proof_of_work_stamp: Vec<u8>,
         message_nonce: Vec<u8>,
-        version: NetworkVersion,
+        versions: Vec<NetworkVersion>,
     ) -> Self {
         Self {
             port,
             public_key,
             proof_of_work_stamp,
             message_nonce,
-            version_length: 0,
-            version,
+            versions,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Readable, Writable)]
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
 pub struct NetworkVersion {
-    pub chain_name_length: u16,
+    pub chain_name_length: u32,
     #[speedy(length = chain_name_length)]
     pub chain_name: String,
     pub distributed_db_version: u16,
@@ -63,10 +66,81 @@ impl speedy::Context for NetworkVersion {
 impl NetworkVersion {
     pub fn new(chain_name: String, distributed_db_version: u16, p2p_version: u16) -> Self {
         Self {
-            chain_name_length: chain_name.as_bytes().len() as u16,
+            chain_name_length: chain_name.len() as u32,
             chain_name,
             distributed_db_version,
             p2p_version,
         }
     }
 }
+
+impl fmt::Display for NetworkVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (distributed_db {}, p2p {})",
+            self.chain_name, self.distributed_db_version, self.p2p_version
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use speedy::Endianness;
+
+    fn sample_message(versions: Vec<NetworkVersion>) -> ConnectionMessage {
+        ConnectionMessage::new(9732, vec![1; 32], vec![2; 24], vec![3; 24], versions)
+    }
+
+    #[test]
+    fn test_connection_message_encoding() {
+        let msg = sample_message(vec![NetworkVersion::new("TEZOS".to_string(), 2, 1)]);
+        let bytes = msg.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+
+        // the chain name has a 4 bytes length prefix
+        assert_eq!(bytes[82..86], [0, 0, 0, 5]);
+        assert_eq!(&bytes[86..91], b"TEZOS");
+        assert_eq!(bytes[91..], [0, 2, 0, 1]);
+    }
+
+    #[test]
+    fn test_connection_message_octez_layout() {
+        // Field by field after octez `Connection_message.encoding`
+        let octez = hex::decode(concat!(
+            "2604",
+            "17f7d11892274a7230d969aa1335d25e637f43087b76d0e24a1a8b7d03168f5c",
+            "b6a4a80d765047918b037c85958c41096326a4b52ff0377e",
+            "000102030405060708090a0b0c0d0e0f1011121314151617",
+            "0000000d",
+            "54455a4f535f4d41494e4e4554",
+            "0002",
+            "0001",
+        ))
+        .unwrap();
+
+        let decoded =
+            ConnectionMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &octez).unwrap();
+        assert_eq!(decoded.port, 9732);
+        assert_eq!(
+            decoded.versions,
+            vec![NetworkVersion::new("TEZOS_MAINNET".to_string(), 2, 1)]
+        );
+        assert_eq!(
+            decoded.write_to_vec_with_ctx(Endianness::BigEndian).unwrap(),
+            octez
+        );
+    }
+
+    #[test]
+    fn test_connection_message_round_trip() {
+        let msg = sample_message(vec![
+            NetworkVersion::new("TEZOS_MAINNET".to_string(), 2, 1),
+            NetworkVersion::new("TEZOS_MAINNET".to_string(), 1, 0),
+        ]);
+        let bytes = msg.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        let decoded =
+            ConnectionMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, msg);
+    }
+}
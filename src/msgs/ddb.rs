@@ -0,0 +1,288 @@
+//! Distributed-db messages, used to exchange chain data once the handshake is done.
+//! They are dispatched by [`super::peer::PeerMessage`] with the octez tag values.
+
+use super::{
+    block_header::BlockHeader,
+    dynamic::{Dynamic, DynamicList},
+};
+use crate::crypto::hash::{BlockHash, ChainId, OperationHash, OperationListListHash, ProtocolHash};
+use speedy::{Context, Readable, Reader, Writable, Writer};
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct GetCurrentBranchMessage {
+    pub chain_id: ChainId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct CurrentBranchMessage {
+    pub chain_id: ChainId,
+    pub current_branch: BlockLocator,
+}
+
+/// Current head of a node followed by a sparse history of its predecessors
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct BlockLocator {
+    pub current_head: Dynamic<BlockHeader>,
+    /// Predecessors of the head, they fill the rest of the message
+    #[speedy(length = ..)]
+    pub history: Vec<BlockHash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct DeactivateMessage {
+    pub chain_id: ChainId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct GetCurrentHeadMessage {
+    pub chain_id: ChainId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct CurrentHeadMessage {
+    pub chain_id: ChainId,
+    pub current_block_header: Dynamic<BlockHeader>,
+    pub current_mempool: Mempool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Readable, Writable)]
+pub struct Mempool {
+    pub known_valid: DynamicList<OperationHash>,
+    /// octez wraps this list in a second size prefix
+    pub pending: Dynamic<DynamicList<OperationHash>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct GetBlockHeadersMessage {
+    pub get_block_headers: DynamicList<BlockHash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct BlockHeaderMessage {
+    pub block_header: BlockHeader,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct GetOperationsMessage {
+    pub get_operations: DynamicList<OperationHash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct OperationMessage {
+    pub operation: Operation,
+}
+
+/// Shell part of an operation, the protocol specific data stays opaque
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct Operation {
+    pub branch: BlockHash,
+    #[speedy(length = ..)]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct GetProtocolsMessage {
+    pub get_protocols: DynamicList<ProtocolHash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct ProtocolMessage {
+    /// Encoded protocol sources, they fill the rest of the message
+    #[speedy(length = ..)]
+    pub protocol: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct GetOperationsForBlocksMessage {
+    pub get_operations_for_blocks: DynamicList<OperationsForBlock>,
+}
+
+/// Operations of a block validation pass
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct OperationsForBlock {
+    pub hash: BlockHash,
+    pub validation_pass: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct OperationsForBlocksMessage {
+    pub operations_for_block: OperationsForBlock,
+    pub operation_hashes_path: Path,
+    /// Operations of the validation pass, they fill the rest of the message
+    #[speedy(length = ..)]
+    pub operations: Vec<Dynamic<Operation>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct GetPredecessorHeaderMessage {
+    pub block_hash: BlockHash,
+    pub offset: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct PredecessorHeaderMessage {
+    pub block_hash: BlockHash,
+    pub offset: i32,
+    pub header: Dynamic<BlockHeader>,
+}
+
+/// Merkle path from an operation list to the operations hash of a block
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Path(pub Vec<PathItem>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathItem {
+    /// The path goes left, `right` is the sibling hash
+    Left { right: OperationListListHash },
+    /// The path goes right, `left` is the sibling hash
+    Right { left: OperationListListHash },
+}
+
+const PATH_LEFT_TAG: u8 = 0xF0;
+const PATH_RIGHT_TAG: u8 = 0x0F;
+const PATH_OP_TAG: u8 = 0x00;
+
+// The path is encoded recursively: a left node writes its sub path before the
+// right hash, so those hashes come after the final Op tag, innermost first.
+impl<'a, C: Context> Readable<'a, C> for Path {
+    fn read_from<R: Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+        let mut items = Vec::new();
+        loop {
+            match reader.read_u8()? {
+                PATH_LEFT_TAG => items.push(None),
+                PATH_RIGHT_TAG => items.push(Some(PathItem::Right {
+                    left: reader.read_value()?,
+                })),
+                PATH_OP_TAG => break,
+                tag => {
+                    return Err(
+                        speedy::Error::custom(format!("Unknown path tag {:#04x}", tag)).into(),
+                    )
+                }
+            }
+        }
+        for item in items.iter_mut().rev() {
+            if item.is_none() {
+                *item = Some(PathItem::Left {
+                    right: reader.read_value()?,
+                });
+            }
+        }
+        Ok(Path(items.into_iter().flatten().collect()))
+    }
+}
+
+impl<C: Context> Writable<C> for Path {
+    fn write_to<T: ?Sized + Writer<C>>(&self, writer: &mut T) -> Result<(), C::Error> {
+        for item in &self.0 {
+            match item {
+                PathItem::Left { .. } => writer.write_u8(PATH_LEFT_TAG)?,
+                PathItem::Right { left } => {
+                    writer.write_u8(PATH_RIGHT_TAG)?;
+                    writer.write_value(left)?;
+                }
+            }
+        }
+        writer.write_u8(PATH_OP_TAG)?;
+        for item in self.0.iter().rev() {
+            if let PathItem::Left { right } = item {
+                writer.write_value(right)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msgs::block_header::sample_block_header;
+    use speedy::Endianness;
+
+    #[test]
+    fn test_path_encoding() {
+        let path = Path(vec![
+            PathItem::Left {
+                right: [1; 32].into(),
+            },
+            PathItem::Right {
+                left: [2; 32].into(),
+            },
+            PathItem::Left {
+                right: [3; 32].into(),
+            },
+        ]);
+        let bytes = path.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+
+        let mut expected = vec![PATH_LEFT_TAG, PATH_RIGHT_TAG];
+        expected.extend_from_slice(&[2; 32]);
+        expected.extend_from_slice(&[PATH_LEFT_TAG, PATH_OP_TAG]);
+        expected.extend_from_slice(&[3; 32]);
+        expected.extend_from_slice(&[1; 32]);
+        assert_eq!(bytes, expected);
+
+        let decoded = Path::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, path);
+    }
+
+    #[test]
+    fn test_path_unknown_tag() {
+        assert!(Path::read_from_buffer_with_ctx(Endianness::BigEndian, &[0x42]).is_err());
+    }
+
+    #[test]
+    fn test_current_head_encoding() {
+        let msg = CurrentHeadMessage {
+            chain_id: [0x7a, 0x06, 0xa7, 0x70].into(),
+            current_block_header: Dynamic(sample_block_header()),
+            current_mempool: Mempool {
+                known_valid: DynamicList(vec![[1; 32].into()]),
+                pending: Dynamic(DynamicList(vec![])),
+            },
+        };
+        let bytes = msg.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+
+        let header = sample_block_header()
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .unwrap();
+        let mut expected = vec![0x7a, 0x06, 0xa7, 0x70];
+        expected.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        expected.extend_from_slice(&header);
+        expected.extend_from_slice(&[0, 0, 0, 32]);
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 0]);
+        assert_eq!(bytes, expected);
+
+        let decoded =
+            CurrentHeadMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn test_operations_for_blocks_round_trip() {
+        let msg = OperationsForBlocksMessage {
+            operations_for_block: OperationsForBlock {
+                hash: [4; 32].into(),
+                validation_pass: 3,
+            },
+            operation_hashes_path: Path(vec![PathItem::Right {
+                left: [5; 32].into(),
+            }]),
+            operations: vec![
+                Dynamic(Operation {
+                    branch: [6; 32].into(),
+                    data: vec![1, 2, 3],
+                }),
+                Dynamic(Operation {
+                    branch: [7; 32].into(),
+                    data: vec![],
+                }),
+            ],
+        };
+        let bytes = msg.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        let decoded =
+            OperationsForBlocksMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes)
+                .unwrap();
+        assert_eq!(decoded, msg);
+    }
+}
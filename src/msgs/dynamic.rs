@@ -0,0 +1,123 @@
+use speedy::{Context, Endianness, Readable, Reader, Writable, Writer};
+
+/// A value prefixed with its encoded size in bytes, it must consume exactly that many bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dynamic<T>(pub T);
+
+/// A list prefixed with its total encoded size in bytes, elements are read until it is consumed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicList<T>(pub Vec<T>);
+
+impl<T> Default for DynamicList<T> {
+    fn default() -> Self {
+        DynamicList(Vec::new())
+    }
+}
+
+fn read_sized<'a, C: Context, R: Reader<'a, C>>(reader: &mut R) -> Result<Vec<u8>, C::Error> {
+    let size = reader.read_u32()? as usize;
+    reader.read_vec(size)
+}
+
+fn write_sized<C: Context, W: ?Sized + Writer<C>>(
+    writer: &mut W,
+    bytes: &[u8],
+) -> Result<(), C::Error> {
+    let size = u32::try_from(bytes.len())
+        .map_err(|_| speedy::Error::custom("Dynamic field larger than 4 GiB"))?;
+    writer.write_u32(size)?;
+    writer.write_bytes(bytes)
+}
+
+impl<'a, C, T> Readable<'a, C> for Dynamic<T>
+where
+    C: Context,
+    T: for<'b> Readable<'b, Endianness>,
+{
+    fn read_from<R: Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+        let endianness = reader.endianness();
+        let bytes = read_sized(reader)?;
+        let (value, read) = T::read_with_length_from_buffer_with_ctx(endianness, &bytes);
+        let value = value?;
+        if read != bytes.len() {
+            return Err(speedy::Error::custom("Dynamic field size mismatch").into());
+        }
+        Ok(Dynamic(value))
+    }
+}
+
+impl<C: Context, T: Writable<Endianness>> Writable<C> for Dynamic<T> {
+    fn write_to<W: ?Sized + Writer<C>>(&self, writer: &mut W) -> Result<(), C::Error> {
+        let bytes = self.0.write_to_vec_with_ctx(writer.endianness())?;
+        write_sized(writer, &bytes)
+    }
+}
+
+impl<'a, C, T> Readable<'a, C> for DynamicList<T>
+where
+    C: Context,
+    T: for<'b> Readable<'b, Endianness>,
+{
+    fn read_from<R: Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+        let endianness = reader.endianness();
+        let bytes = read_sized(reader)?;
+        let mut items = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let (item, read) = T::read_with_length_from_buffer_with_ctx(endianness, &bytes[pos..]);
+            items.push(item?);
+            pos += read;
+        }
+        Ok(DynamicList(items))
+    }
+}
+
+impl<C: Context, T: Writable<Endianness>> Writable<C> for DynamicList<T> {
+    fn write_to<W: ?Sized + Writer<C>>(&self, writer: &mut W) -> Result<(), C::Error> {
+        let endianness = writer.endianness();
+        let mut bytes = Vec::new();
+        for item in &self.0 {
+            bytes.extend(item.write_to_vec_with_ctx(endianness)?);
+        }
+        write_sized(writer, &bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dynamic_list_encoding() {
+        let list = DynamicList(vec![0x0102u16, 0x0304]);
+        let bytes = list.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 4, 1, 2, 3, 4]);
+        let decoded =
+            DynamicList::<u16>::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, list);
+    }
+
+    #[test]
+    fn test_dynamic_list_truncated_item() {
+        let bytes = [0, 0, 0, 3, 1, 2, 3];
+        assert!(
+            DynamicList::<u16>::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).is_err()
+        );
+    }
+
+    #[test]
+    fn test_dynamic_encoding() {
+        let value = Dynamic(0x01020304u32);
+        let bytes = value.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 4, 1, 2, 3, 4]);
+        let decoded =
+            Dynamic::<u32>::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_dynamic_size_mismatch() {
+        let bytes = [0, 0, 0, 5, 1, 2, 3, 4, 5];
+        assert!(Dynamic::<u32>::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).is_err());
+    }
+}
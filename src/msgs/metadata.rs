@@ -1,9 +1,9 @@
 use speedy::{Readable, Writable};
 
-#[derive(Debug, PartialEq, Readable, Writable)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Readable, Writable)]
 pub struct MetadataMessage {
-    disable_mempool: bool,
-    private_node: bool,
+    pub disable_mempool: bool,
+    pub private_node: bool,
 }
 
 impl MetadataMessage {
@@ -1,3 +1,8 @@
 pub mod ack;
+pub mod block_header;
 pub mod connection;
+pub mod ddb;
+pub mod dynamic;
 pub mod metadata;
+pub mod peer;
+pub mod point;
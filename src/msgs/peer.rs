@@ -0,0 +1,232 @@
+use super::ddb::{
+    BlockHeaderMessage, CurrentBranchMessage, CurrentHeadMessage, DeactivateMessage,
+    GetBlockHeadersMessage, GetCurrentBranchMessage, GetCurrentHeadMessage,
+    GetOperationsForBlocksMessage, GetOperationsMessage, GetPredecessorHeaderMessage,
+    GetProtocolsMessage, OperationMessage, OperationsForBlocksMessage, PredecessorHeaderMessage,
+    ProtocolMessage,
+};
+use crate::crypto::{hash::ChainId, peer_id::PeerId};
+use speedy::{Readable, Writable};
+
+/// Messages exchanged once the handshake is done, both the p2p layer
+/// ones and the distributed-db ones, each one is sent as a single
+/// application message over chunks.
+///
+/// Tezos has no dedicated ping or keepalive message, a connection
+/// stays open as long as either side keeps sending messages.
+#[derive(Debug, Clone, PartialEq, Readable, Writable)]
+#[speedy(tag_type = u16)]
+pub enum PeerMessage {
+    /// The remote is closing the connection
+    #[speedy(tag = 0x01)]
+    Disconnect,
+    /// Asks the remote for points to connect to, it answers with an Advertise
+    #[speedy(tag = 0x02)]
+    Bootstrap,
+    #[speedy(tag = 0x03)]
+    Advertise(AdvertiseMessage),
+    /// Proposes to replace our connection with the given peer
+    #[speedy(tag = 0x04)]
+    SwapRequest(SwapMessage),
+    /// Accepts a swap, giving a peer of our own in exchange
+    #[speedy(tag = 0x05)]
+    SwapAck(SwapMessage),
+    #[speedy(tag = 0x10)]
+    GetCurrentBranch(GetCurrentBranchMessage),
+    #[speedy(tag = 0x11)]
+    CurrentBranch(CurrentBranchMessage),
+    /// The sender stops following the chain
+    #[speedy(tag = 0x12)]
+    Deactivate(DeactivateMessage),
+    #[speedy(tag = 0x13)]
+    GetCurrentHead(GetCurrentHeadMessage),
+    #[speedy(tag = 0x14)]
+    CurrentHead(CurrentHeadMessage),
+    #[speedy(tag = 0x20)]
+    GetBlockHeaders(GetBlockHeadersMessage),
+    #[speedy(tag = 0x21)]
+    BlockHeader(BlockHeaderMessage),
+    #[speedy(tag = 0x30)]
+    GetOperations(GetOperationsMessage),
+    #[speedy(tag = 0x31)]
+    Operation(OperationMessage),
+    #[speedy(tag = 0x40)]
+    GetProtocols(GetProtocolsMessage),
+    #[speedy(tag = 0x41)]
+    Protocol(ProtocolMessage),
+    #[speedy(tag = 0x60)]
+    GetOperationsForBlocks(GetOperationsForBlocksMessage),
+    #[speedy(tag = 0x61)]
+    OperationsForBlocks(OperationsForBlocksMessage),
+    #[speedy(tag = 0x90)]
+    GetPredecessorHeader(GetPredecessorHeaderMessage),
+    #[speedy(tag = 0x91)]
+    PredecessorHeader(PredecessorHeaderMessage),
+}
+
+impl PeerMessage {
+    /// Chain the message is about, for the messages bound to a chain
+    pub fn chain_id(&self) -> Option<&ChainId> {
+        match self {
+            PeerMessage::GetCurrentBranch(msg) => Some(&msg.chain_id),
+            PeerMessage::CurrentBranch(msg) => Some(&msg.chain_id),
+            PeerMessage::Deactivate(msg) => Some(&msg.chain_id),
+            PeerMessage::GetCurrentHead(msg) => Some(&msg.chain_id),
+            PeerMessage::CurrentHead(msg) => Some(&msg.chain_id),
+            _ => None,
+        }
+    }
+}
+
+/// `ip:port` points the sender knows about, they fill the rest of the message
+#[derive(Debug, Clone, Default, PartialEq, Eq, Readable, Writable)]
+pub struct AdvertiseMessage {
+    #[speedy(length = ..)]
+    pub points: Vec<String>,
+}
+
+impl AdvertiseMessage {
+    pub fn new(points: Vec<String>) -> Self {
+        Self { points }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Readable, Writable)]
+pub struct SwapMessage {
+    pub point: String,
+    pub peer_id: PeerId,
+}
+
+impl SwapMessage {
+    pub fn new(point: String, peer_id: PeerId) -> Self {
+        Self { point, peer_id }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msgs::{
+        block_header::sample_block_header,
+        ddb::{BlockLocator, Operation, OperationsForBlock},
+        dynamic::{Dynamic, DynamicList},
+    };
+    use speedy::Endianness;
+
+    fn round_trip(msg: PeerMessage) -> Vec<u8> {
+        let bytes = msg.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        let decoded =
+            PeerMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, msg);
+        bytes
+    }
+
+    #[test]
+    fn test_disconnect_and_bootstrap_encoding() {
+        assert_eq!(round_trip(PeerMessage::Disconnect), vec![0x00, 0x01]);
+        assert_eq!(round_trip(PeerMessage::Bootstrap), vec![0x00, 0x02]);
+    }
+
+    #[test]
+    fn test_advertise_encoding() {
+        let msg = PeerMessage::Advertise(AdvertiseMessage::new(vec![
+            "1.2.3.4:9732".to_string(),
+            "[::1]:9733".to_string(),
+        ]));
+        let mut expected = vec![0x00, 0x03, 0x00, 0x00, 0x00, 0x0c];
+        expected.extend_from_slice(b"1.2.3.4:9732");
+        expected.extend_from_slice(&[0x00, 0x00, 0x00, 0x0a]);
+        expected.extend_from_slice(b"[::1]:9733");
+        assert_eq!(round_trip(msg), expected);
+    }
+
+    #[test]
+    fn test_empty_advertise() {
+        let bytes = round_trip(PeerMessage::Advertise(AdvertiseMessage::default()));
+        assert_eq!(bytes, vec![0x00, 0x03]);
+    }
+
+    #[test]
+    fn test_swap_encoding() {
+        let peer_id: PeerId = "idsfYM6UbG2nhNS1dqhsJEchaDhmd9".parse().unwrap();
+        let swap = SwapMessage::new("1.2.3.4:9732".to_string(), peer_id);
+
+        let mut expected = vec![0x00, 0x04, 0x00, 0x00, 0x00, 0x0c];
+        expected.extend_from_slice(b"1.2.3.4:9732");
+        expected.extend_from_slice(peer_id.as_ref());
+        assert_eq!(round_trip(PeerMessage::SwapRequest(swap.clone())), expected);
+
+        expected[1] = 0x05;
+        assert_eq!(round_trip(PeerMessage::SwapAck(swap)), expected);
+    }
+
+    #[test]
+    fn test_get_current_head_encoding() {
+        let msg = PeerMessage::GetCurrentHead(GetCurrentHeadMessage {
+            chain_id: [0x7a, 0x06, 0xa7, 0x70].into(),
+        });
+        assert_eq!(round_trip(msg), vec![0x00, 0x13, 0x7a, 0x06, 0xa7, 0x70]);
+    }
+
+    #[test]
+    fn test_get_block_headers_encoding() {
+        let msg = PeerMessage::GetBlockHeaders(GetBlockHeadersMessage {
+            get_block_headers: DynamicList(vec![[1; 32].into(), [2; 32].into()]),
+        });
+        let mut expected = vec![0x00, 0x20, 0x00, 0x00, 0x00, 0x40];
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(&[2; 32]);
+        assert_eq!(round_trip(msg), expected);
+    }
+
+    #[test]
+    fn test_ddb_messages_round_trip() {
+        round_trip(PeerMessage::CurrentBranch(CurrentBranchMessage {
+            chain_id: [1, 2, 3, 4].into(),
+            current_branch: BlockLocator {
+                current_head: Dynamic(sample_block_header()),
+                history: vec![[1; 32].into(), [2; 32].into()],
+            },
+        }));
+        round_trip(PeerMessage::Deactivate(DeactivateMessage {
+            chain_id: [1, 2, 3, 4].into(),
+        }));
+        round_trip(PeerMessage::BlockHeader(BlockHeaderMessage {
+            block_header: sample_block_header(),
+        }));
+        round_trip(PeerMessage::Operation(OperationMessage {
+            operation: Operation {
+                branch: [3; 32].into(),
+                data: vec![1, 2, 3],
+            },
+        }));
+        round_trip(PeerMessage::GetOperationsForBlocks(
+            GetOperationsForBlocksMessage {
+                get_operations_for_blocks: DynamicList(vec![OperationsForBlock {
+                    hash: [4; 32].into(),
+                    validation_pass: -1,
+                }]),
+            },
+        ));
+        round_trip(PeerMessage::PredecessorHeader(PredecessorHeaderMessage {
+            block_hash: [5; 32].into(),
+            offset: 8,
+            header: Dynamic(sample_block_header()),
+        }));
+    }
+
+    #[test]
+    fn test_decode_unknown_tag() {
+        let result = PeerMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &[0x00, 0x06]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_decode_truncated_swap() {
+        let mut bytes = vec![0x00, 0x04, 0x00, 0x00, 0x00, 0x0c];
+        bytes.extend_from_slice(b"1.2.3.4:9732");
+        bytes.extend_from_slice(&[0u8; 8]);
+        let result = PeerMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes);
+        assert!(result.is_err());
+    }
+}
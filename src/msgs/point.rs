@@ -0,0 +1,76 @@
+use speedy::{Context, Readable, Reader, Writable, Writer};
+use std::net::SocketAddr;
+
+/// Length of the size prefix of a dynamic field
+const SIZE_PREFIX_LENGTH: usize = 4;
+
+/// List of `ip:port` points, encoded as its total size in bytes
+/// followed by each point as a length prefixed string.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PointList(pub Vec<String>);
+
+impl PointList {
+    /// Points that parse as socket addresses, malformed ones are skipped
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        self.0.iter().filter_map(|p| p.parse().ok()).collect()
+    }
+}
+
+impl From<&[SocketAddr]> for PointList {
+    fn from(addrs: &[SocketAddr]) -> Self {
+        PointList(addrs.iter().map(|a| a.to_string()).collect())
+    }
+}
+
+impl<'a, C: Context> Readable<'a, C> for PointList {
+    fn read_from<R: Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+        let size = reader.read_u32()? as usize;
+        let mut points = Vec::new();
+        let mut read = 0;
+        while read < size {
+            let point: String = reader.read_value()?;
+            read += SIZE_PREFIX_LENGTH + point.len();
+            points.push(point);
+        }
+        if read != size {
+            return Err(speedy::Error::custom("Point list size mismatch").into());
+        }
+        Ok(PointList(points))
+    }
+}
+
+impl<C: Context> Writable<C> for PointList {
+    fn write_to<T: ?Sized + Writer<C>>(&self, writer: &mut T) -> Result<(), C::Error> {
+        let size: usize = self.0.iter().map(|p| SIZE_PREFIX_LENGTH + p.len()).sum();
+        writer.write_u32(size as u32)?;
+        for point in &self.0 {
+            writer.write_value(point)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use speedy::Endianness;
+
+    #[test]
+    fn test_point_list_encoding() {
+        let points = PointList(vec!["1.2.3.4:9732".to_string(), "[::1]:9733".to_string()]);
+        let bytes = points.write_to_vec_with_ctx(Endianness::BigEndian).unwrap();
+        assert_eq!(bytes[..4], [0, 0, 0, 30]);
+        assert_eq!(bytes[4..8], [0, 0, 0, 12]);
+        assert_eq!(&bytes[8..20], b"1.2.3.4:9732");
+
+        let decoded = PointList::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).unwrap();
+        assert_eq!(decoded, points);
+        assert_eq!(decoded.socket_addrs().len(), 2);
+    }
+
+    #[test]
+    fn test_point_list_size_mismatch() {
+        let bytes = [0, 0, 0, 5, 0, 0, 0, 3, b'a', b'b', b'c'];
+        assert!(PointList::read_from_buffer_with_ctx(Endianness::BigEndian, &bytes).is_err());
+    }
+}
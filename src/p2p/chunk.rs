@@ -0,0 +1,145 @@
+use sodiumoxide::crypto::box_;
+use thiserror::Error;
+
+/// Largest binary chunk content, its size is encoded on 2 bytes
+pub const MAX_CHUNK_SIZE: usize = u16::MAX as usize;
+/// Authentication tag added by the crypto box to every encrypted chunk
+pub const MAC_LENGTH: usize = box_::MACBYTES;
+/// Largest plaintext fitting in one encrypted chunk
+pub const MAX_PLAINTEXT_CHUNK_SIZE: usize = MAX_CHUNK_SIZE - MAC_LENGTH;
+/// Largest application message accepted unless configured otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+/// Application messages start with their size on 4 bytes
+const MESSAGE_SIZE_PREFIX_LENGTH: usize = 4;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ChunkError {
+    #[error("Chunk of {size} bytes exceeds the maximum of {MAX_CHUNK_SIZE} bytes")]
+    ChunkTooLarge { size: usize },
+    #[error("Message of {size} bytes exceeds the maximum of {max} bytes")]
+    MessageTooLarge { size: usize, max: usize },
+}
+
+/// Prefixes `message` with its size and splits it in plaintext chunks
+/// small enough to be encrypted into a single binary chunk each.
+pub fn split_message(message: &[u8], max_message_size: usize) -> Result<Vec<Vec<u8>>, ChunkError> {
+    if message.len() > max_message_size || message.len() > u32::MAX as usize {
+        return Err(ChunkError::MessageTooLarge {
+            size: message.len(),
+            max: max_message_size,
+        });
+    }
+
+    let mut payload = Vec::with_capacity(MESSAGE_SIZE_PREFIX_LENGTH + message.len());
+    payload.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    payload.extend_from_slice(message);
+    Ok(payload
+        .chunks(MAX_PLAINTEXT_CHUNK_SIZE)
+        .map(|chunk| chunk.to_vec())
+        .collect())
+}
+
+/// Collects decrypted chunks until whole messages can be read back.
+#[derive(Debug)]
+pub struct MessageReassembler {
+    max_message_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl MessageReassembler {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Pops the next complete message, `None` means more chunks are needed.
+    pub fn next_message(&mut self) -> Result<Option<Vec<u8>>, ChunkError> {
+        let Some(prefix) = self.buffer.get(..MESSAGE_SIZE_PREFIX_LENGTH) else {
+            return Ok(None);
+        };
+
+        let size = u32::from_be_bytes(prefix.try_into().expect("4 bytes prefix")) as usize;
+        if size > self.max_message_size {
+            return Err(ChunkError::MessageTooLarge {
+                size,
+                max: self.max_message_size,
+            });
+        }
+        if self.buffer.len() < MESSAGE_SIZE_PREFIX_LENGTH + size {
+            return Ok(None);
+        }
+
+        let rest = self.buffer.split_off(MESSAGE_SIZE_PREFIX_LENGTH + size);
+        let message = std::mem::replace(&mut self.buffer, rest);
+        Ok(Some(message[MESSAGE_SIZE_PREFIX_LENGTH..].to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_small_message() {
+        let chunks = split_message(b"hello", DEFAULT_MAX_MESSAGE_SIZE).unwrap();
+        assert_eq!(chunks, vec![vec![0, 0, 0, 5, b'h', b'e', b'l', b'l', b'o']]);
+    }
+
+    #[test]
+    fn test_split_large_message() {
+        let message = vec![7u8; 3 * MAX_PLAINTEXT_CHUNK_SIZE];
+        let chunks = split_message(&message, DEFAULT_MAX_MESSAGE_SIZE).unwrap();
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|c| c.len() <= MAX_PLAINTEXT_CHUNK_SIZE));
+        assert_eq!(chunks[3].len(), MESSAGE_SIZE_PREFIX_LENGTH);
+    }
+
+    #[test]
+    fn test_split_message_too_large() {
+        assert_eq!(
+            split_message(&[0u8; 11], 10),
+            Err(ChunkError::MessageTooLarge { size: 11, max: 10 })
+        );
+    }
+
+    #[test]
+    fn test_reassemble_message() {
+        let message: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        let mut reassembler = MessageReassembler::new(DEFAULT_MAX_MESSAGE_SIZE);
+        let chunks = split_message(&message, DEFAULT_MAX_MESSAGE_SIZE).unwrap();
+        let (last, first) = chunks.split_last().unwrap();
+        for chunk in first {
+            reassembler.push(chunk);
+            assert_eq!(reassembler.next_message(), Ok(None));
+        }
+        reassembler.push(last);
+        assert_eq!(reassembler.next_message(), Ok(Some(message)));
+        assert_eq!(reassembler.next_message(), Ok(None));
+    }
+
+    #[test]
+    fn test_reassemble_messages_sharing_a_chunk() {
+        let mut reassembler = MessageReassembler::new(DEFAULT_MAX_MESSAGE_SIZE);
+        reassembler.push(&[0, 0, 0, 1, 42, 0, 0, 0, 2, 1]);
+        assert_eq!(reassembler.next_message(), Ok(Some(vec![42])));
+        assert_eq!(reassembler.next_message(), Ok(None));
+        reassembler.push(&[2]);
+        assert_eq!(reassembler.next_message(), Ok(Some(vec![1, 2])));
+    }
+
+    #[test]
+    fn test_reassemble_message_too_large() {
+        let mut reassembler = MessageReassembler::new(10);
+        reassembler.push(&[0, 0, 0, 11]);
+        assert_eq!(
+            reassembler.next_message(),
+            Err(ChunkError::MessageTooLarge { size: 11, max: 10 })
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use super::{chunk::DEFAULT_MAX_MESSAGE_SIZE, version::SupportedVersions};
+use crate::constants::{BOOTSTRAP_DEFAULT_PORT, DEFAULT_EXPECTED_POW};
+use crate::crypto::hash::ChainId;
+use crate::msgs::metadata::MetadataMessage;
+use std::{net::SocketAddr, ops::RangeInclusive, time::Duration};
+
+/// Time allowed to open the TCP connection, as octez `connection-timeout`
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time allowed to each handshake step, as octez `authentication-timeout`
+pub const DEFAULT_HANDSHAKE_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long each stage of a connection may take before we give up on the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Opening the TCP connection
+    pub connect: Duration,
+    /// Exchanging the clear connection messages
+    pub connection_message: Duration,
+    /// Exchanging the metadata messages
+    pub metadata: Duration,
+    /// Exchanging the acknowledgements
+    pub ack: Duration,
+    /// Longest wait for the next chunk once connected, unlimited when unset
+    pub idle: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: DEFAULT_CONNECT_TIMEOUT,
+            connection_message: DEFAULT_HANDSHAKE_STEP_TIMEOUT,
+            metadata: DEFAULT_HANDSHAKE_STEP_TIMEOUT,
+            ack: DEFAULT_HANDSHAKE_STEP_TIMEOUT,
+            idle: None,
+        }
+    }
+}
+
+/// Settings applied to every handshake, outgoing or incoming.
+#[derive(Debug, Clone)]
+pub struct PeerConfig {
+    /// Chain name and version ranges we negotiate with
+    pub versions: SupportedVersions,
+    /// Minimal proof-of-work difficulty a remote peer must reach
+    pub expected_pow: f64,
+    /// Metadata flags announced to the remote
+    pub metadata: MetadataMessage,
+    /// Points suggested to the peers we refuse
+    pub known_points: Vec<SocketAddr>,
+    /// Largest application message we send or accept, in bytes
+    pub max_message_size: usize,
+    /// Chain we follow, messages about another chain are rejected when set
+    pub chain_id: Option<ChainId>,
+    /// Limits on the time each connection stage may take
+    pub timeouts: Timeouts,
+    /// Port we accept connections on, announced when we dial a peer
+    pub listening_port: u16,
+}
+
+impl PeerConfig {
+    pub fn new(chain_name: String) -> Self {
+        Self {
+            versions: SupportedVersions::new(chain_name),
+            expected_pow: DEFAULT_EXPECTED_POW,
+            metadata: MetadataMessage::default(),
+            known_points: vec![],
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            chain_id: None,
+            timeouts: Timeouts::default(),
+            listening_port: BOOTSTRAP_DEFAULT_PORT,
+        }
+    }
+
+    pub fn with_expected_pow(mut self, expected_pow: f64) -> Self {
+        self.expected_pow = expected_pow;
+        self
+    }
+
+    pub fn with_metadata(mut self, disable_mempool: bool, private_node: bool) -> Self {
+        self.metadata = MetadataMessage::new(disable_mempool, private_node);
+        self
+    }
+
+    pub fn with_known_points(mut self, known_points: Vec<SocketAddr>) -> Self {
+        self.known_points = known_points;
+        self
+    }
+
+    pub fn with_chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    pub fn with_listening_port(mut self, listening_port: u16) -> Self {
+        self.listening_port = listening_port;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = timeout;
+        self
+    }
+
+    /// Same time allowed to every handshake step
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connection_message = timeout;
+        self.timeouts.metadata = timeout;
+        self.timeouts.ack = timeout;
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.idle = Some(timeout);
+        self
+    }
+
+    pub fn with_distributed_db_versions(mut self, versions: RangeInclusive<u16>) -> Self {
+        self.versions.distributed_db_versions = versions;
+        self
+    }
+
+    pub fn with_p2p_versions(mut self, versions: RangeInclusive<u16>) -> Self {
+        self.versions.p2p_versions = versions;
+        self
+    }
+}
@@ -1,10 +1,59 @@
 use std::net::{SocketAddr, ToSocketAddrs};
 
-pub fn lookup_active_nodes(dns: &[&str], port: u16) -> Vec<SocketAddr> {
-    dns.iter()
-        .flat_map(|d| {
-            let t = (*d, port);
-            ToSocketAddrs::to_socket_addrs(&t).unwrap_or_default()
+/// Resolves `host` or `host:port` points, `default_port` is used for the ones without a port.
+/// Points that fail to resolve are skipped.
+pub fn lookup_points(points: &[String], default_port: u16) -> Vec<SocketAddr> {
+    points
+        .iter()
+        .flat_map(|point| {
+            let (host, port) = split_point(point, default_port);
+            ToSocketAddrs::to_socket_addrs(&(host, port)).unwrap_or_default()
         })
         .collect::<Vec<_>>()
 }
+
+fn split_point(point: &str, default_port: u16) -> (&str, u16) {
+    let (host, port) = match point.rsplit_once(':') {
+        // A bare IPv6 address has colons but no port
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+            match port.parse::<u16>() {
+                Ok(port) => (host, port),
+                Err(_) => (point, default_port),
+            }
+        }
+        _ => (point, default_port),
+    };
+    (host.trim_start_matches('[').trim_end_matches(']'), port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_point() {
+        assert_eq!(
+            split_point("boot.tzboot.net", 9732),
+            ("boot.tzboot.net", 9732)
+        );
+        assert_eq!(
+            split_point("ghostnet.stakenow.de:9733", 9732),
+            ("ghostnet.stakenow.de", 9733)
+        );
+        assert_eq!(split_point("[::1]:9733", 9732), ("::1", 9733));
+        assert_eq!(split_point("::1", 9732), ("::1", 9732));
+        assert_eq!(split_point("[::1]", 9732), ("::1", 9732));
+    }
+
+    #[test]
+    fn test_lookup_points() {
+        let points = vec!["127.0.0.1".to_string(), "127.0.0.1:9733".to_string()];
+        assert_eq!(
+            lookup_points(&points, 9732),
+            vec![
+                "127.0.0.1:9732".parse::<SocketAddr>().unwrap(),
+                "127.0.0.1:9733".parse().unwrap()
+            ]
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use super::peer::{PeerError, PeerInfo, PeerReader, PeerWriter};
+use crate::{crypto::key::CryptoError, msgs::peer::PeerMessage};
+use std::io;
+use thiserror::Error;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Messages buffered in each direction before senders wait
+pub const CHANNEL_CAPACITY: usize = 64;
+
+/// Why a spawned connection stopped
+#[derive(Debug, Error)]
+pub enum TerminationReason {
+    #[error("Connection closed by us")]
+    Closed,
+    #[error("Connection closed by the remote")]
+    RemoteClosed,
+    #[error("Undecodable message from the remote: {0}")]
+    Decode(PeerError),
+    #[error("Connection timed out")]
+    Timeout,
+    #[error("Message authentication failed: {0}")]
+    Crypto(CryptoError),
+    #[error("Connection failed: {0}")]
+    Failed(PeerError),
+    #[error("Incoming messages were no longer received")]
+    InboundDropped,
+}
+
+impl From<PeerError> for TerminationReason {
+    fn from(e: PeerError) -> Self {
+        match e {
+            PeerError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                TerminationReason::RemoteClosed
+            }
+            PeerError::Io(e) if e.kind() == io::ErrorKind::TimedOut => TerminationReason::Timeout,
+            PeerError::Timeout { .. } => TerminationReason::Timeout,
+            PeerError::CryptoFailed(e) => TerminationReason::Crypto(e),
+            e @ (PeerError::MalformedMessage { .. }
+            | PeerError::Chunk(_)
+            | PeerError::UnexpectedChainId { .. }) => TerminationReason::Decode(e),
+            e => TerminationReason::Failed(e),
+        }
+    }
+}
+
+/// A connection running in the background: one task reads and decodes incoming
+/// messages, another one sends the queued outgoing ones. Dropping the handle stops
+/// the incoming stream, so the next message from the remote closes the connection
+/// with a Disconnect, even while [`PeerHandle::sender`] clones are alive.
+pub struct PeerHandle {
+    info: PeerInfo,
+    outbound: mpsc::Sender<PeerMessage>,
+    inbound: mpsc::Receiver<PeerMessage>,
+    task: JoinHandle<TerminationReason>,
+}
+
+impl PeerHandle {
+    pub(super) fn start(info: PeerInfo, reader: PeerReader, writer: PeerWriter) -> Self {
+        let (outbound, outbound_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (inbound_tx, inbound) = mpsc::channel(CHANNEL_CAPACITY);
+
+        let mut reader_task = tokio::spawn(read_loop(reader, inbound_tx));
+        let mut writer_task = tokio::spawn(write_loop(writer, outbound_rx));
+        // Weak so that dropping every sender still closes the connection
+        let disconnect = outbound.downgrade();
+        let task = tokio::spawn(async move {
+            // Whichever side stops first ends the connection, our own close
+            // wins over the remote closing in answer to it
+            let reason = tokio::select! {
+                biased;
+                reason = &mut writer_task => reason,
+                reason = &mut reader_task => reason,
+            };
+            if let Ok(TerminationReason::InboundDropped) = reason {
+                // Nobody takes the remote messages anymore, say goodbye first
+                if let Some(outbound) = disconnect.upgrade() {
+                    let _ = outbound.send(PeerMessage::Disconnect).await;
+                }
+                let _ = (&mut writer_task).await;
+            }
+            reader_task.abort();
+            writer_task.abort();
+            reason.expect("Peer connection task panicked")
+        });
+
+        PeerHandle {
+            info,
+            outbound,
+            inbound,
+            task,
+        }
+    }
+
+    /// What we learned about the remote node during the handshake
+    pub fn info(&self) -> &PeerInfo {
+        &self.info
+    }
+
+    /// Queue of messages to send, sending [`PeerMessage::Disconnect`] closes the connection
+    pub fn sender(&self) -> mpsc::Sender<PeerMessage> {
+        self.outbound.clone()
+    }
+
+    /// Next message from the remote, `None` once the connection stopped
+    pub async fn recv(&mut self) -> Option<PeerMessage> {
+        self.inbound.recv().await
+    }
+
+    /// Sends a Disconnect after the queued messages, then waits for the connection to stop.
+    pub async fn close(self) -> TerminationReason {
+        // Fails only when the writer already stopped, the reason tells why
+        let _ = self.outbound.send(PeerMessage::Disconnect).await;
+        self.terminated().await
+    }
+
+    /// Waits for the connection to stop, without closing it.
+    pub async fn terminated(self) -> TerminationReason {
+        self.task.await.expect("Peer connection task panicked")
+    }
+}
+
+async fn read_loop(
+    mut reader: PeerReader,
+    inbound: mpsc::Sender<PeerMessage>,
+) -> TerminationReason {
+    loop {
+        match reader.recv_message().await {
+            Ok(PeerMessage::Disconnect) => return TerminationReason::RemoteClosed,
+            Ok(message) => {
+                if inbound.send(message).await.is_err() {
+                    return TerminationReason::InboundDropped;
+                }
+            }
+            Err(e) => return e.into(),
+        }
+    }
+}
+
+async fn write_loop(
+    mut writer: PeerWriter,
+    mut outbound: mpsc::Receiver<PeerMessage>,
+) -> TerminationReason {
+    loop {
+        // Every sender being dropped closes the connection too
+        let message = outbound.recv().await.unwrap_or(PeerMessage::Disconnect);
+        if let Err(e) = writer.send_message(&message).await {
+            return e.into();
+        }
+        if message == PeerMessage::Disconnect {
+            return match writer.shutdown().await {
+                Ok(()) => TerminationReason::Closed,
+                Err(e) => e.into(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        msgs::peer::AdvertiseMessage,
+        p2p::{
+            config::PeerConfig,
+            peer::{connected_peers, TimeoutStage},
+        },
+    };
+
+    #[tokio::test]
+    async fn test_spawned_peers_exchange_messages() {
+        let (initiator, responder) =
+            connected_peers(PeerConfig::new("TEZOS_MAINNET".to_string())).await;
+        let mut initiator = initiator.spawn().unwrap();
+        let mut responder = responder.spawn().unwrap();
+        assert_ne!(initiator.info().peer_id, responder.info().peer_id);
+
+        let advertise =
+            PeerMessage::Advertise(AdvertiseMessage::new(vec!["1.2.3.4:9732".to_string()]));
+        initiator
+            .sender()
+            .send(PeerMessage::Bootstrap)
+            .await
+            .unwrap();
+        responder.sender().send(advertise.clone()).await.unwrap();
+        assert_eq!(responder.recv().await, Some(PeerMessage::Bootstrap));
+        assert_eq!(initiator.recv().await, Some(advertise));
+
+        assert!(matches!(initiator.close().await, TerminationReason::Closed));
+        assert_eq!(responder.recv().await, None);
+        assert!(matches!(
+            responder.terminated().await,
+            TerminationReason::RemoteClosed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_spawned_peer_handle_dropped() {
+        let (initiator, responder) =
+            connected_peers(PeerConfig::new("TEZOS_MAINNET".to_string())).await;
+        let initiator = initiator.spawn().unwrap();
+        let responder = responder.spawn().unwrap();
+        // A sender outliving the handle does not keep the connection reading
+        let _sender = initiator.sender();
+        drop(initiator);
+
+        responder
+            .sender()
+            .send(PeerMessage::Bootstrap)
+            .await
+            .unwrap();
+        assert!(matches!(
+            responder.terminated().await,
+            TerminationReason::RemoteClosed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_spawned_peer_remote_dropped() {
+        let (initiator, responder) =
+            connected_peers(PeerConfig::new("TEZOS_MAINNET".to_string())).await;
+        let initiator = initiator.spawn().unwrap();
+        drop(responder);
+        assert!(matches!(
+            initiator.terminated().await,
+            TerminationReason::RemoteClosed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_spawned_peer_undecodable_message() {
+        let (initiator, responder) =
+            connected_peers(PeerConfig::new("TEZOS_MAINNET".to_string())).await;
+        let responder = responder.spawn().unwrap();
+        let (_reader, mut writer) = initiator.split().unwrap();
+        // 0xffff is not a known peer message tag
+        writer.send_chunked(&[0xff, 0xff]).await.unwrap();
+        assert!(matches!(
+            responder.terminated().await,
+            TerminationReason::Decode(PeerError::MalformedMessage { kind: "peer", .. })
+        ));
+    }
+
+    #[test]
+    fn test_termination_reason_from_peer_error() {
+        let eof = io::Error::from(io::ErrorKind::UnexpectedEof);
+        assert!(matches!(
+            TerminationReason::from(PeerError::Io(eof)),
+            TerminationReason::RemoteClosed
+        ));
+        let timeout = io::Error::from(io::ErrorKind::TimedOut);
+        assert!(matches!(
+            TerminationReason::from(PeerError::Io(timeout)),
+            TerminationReason::Timeout
+        ));
+        assert!(matches!(
+            TerminationReason::from(PeerError::Timeout {
+                stage: TimeoutStage::Idle
+            }),
+            TerminationReason::Timeout
+        ));
+        assert!(matches!(
+            TerminationReason::from(PeerError::CryptoFailed(CryptoError::FailedToDecrypt)),
+            TerminationReason::Crypto(CryptoError::FailedToDecrypt)
+        ));
+        assert!(matches!(
+            TerminationReason::from(PeerError::ConnectionFailed),
+            TerminationReason::Failed(PeerError::ConnectionFailed)
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+use super::{
+    config::PeerConfig,
+    peer::{Peer, PeerError},
+};
+use crate::{
+    crypto::{identity::Identity, peer_id::PeerId},
+    msgs::ack::NackMotive,
+};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Pause before accepting again after a failure not tied to a single connection,
+/// such as running out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts connections from other nodes and runs the responder side of
+/// the handshake for each one in its own task. Past `max_connections`
+/// simultaneous handshakes, new peers are refused with a Nack. Only failing
+/// to bind `addr` is an error, a failed accept is logged and the loop goes on.
+pub async fn listen(
+    addr: SocketAddr,
+    identity: Identity,
+    config: PeerConfig,
+    max_connections: usize,
+) -> Result<(), PeerError> {
+    let listener = TcpListener::bind(addr).await.map_err(PeerError::Io)?;
+    println!("Listening on {}... 👂", addr);
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                println!("Failed to accept a connection, Error: {}", e);
+                if !is_connection_error(&e) {
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+                continue;
+            }
+        };
+        println!("Accepted connection from {}... 🛜", remote);
+
+        let identity = identity.clone();
+        let config = config.clone();
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            let refusal = if connections.fetch_add(1, Ordering::SeqCst) >= max_connections {
+                Some(NackMotive::TooManyConnections)
+            } else {
+                None
+            };
+            match handshake_incoming(stream, identity, config, refusal).await {
+                Ok(peer_id) => println!("Handshake with {} ({}) completed! 🎉", peer_id, remote),
+                Err(e) => println!("Handshake with {} failed, Error: {}", remote, e),
+            }
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Whether an accept failure only concerns the connection being accepted, others
+/// (EMFILE, ENFILE, ENOBUFS...) would fail again right away
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+    )
+}
+
+async fn handshake_incoming(
+    stream: TcpStream,
+    identity: Identity,
+    config: PeerConfig,
+    refusal: Option<NackMotive>,
+) -> Result<PeerId, PeerError> {
+    let mut peer = Peer::accept(stream, identity, config)?;
+    match refusal {
+        Some(motive) => peer.refuse(motive).await?,
+        None => peer.handshake().await?,
+    }
+    let peer_id = peer
+        .remote_peer_id()
+        .copied()
+        .ok_or(PeerError::ConnectionFailed)?;
+    peer.desconnect().await?;
+    Ok(peer_id)
+}
@@ -0,0 +1,586 @@
+//! Scriptable fake Tezos node running the responder side of the handshake, so the
+//! initiator side of [`Peer`](super::peer::Peer) can be tested end to end over an
+//! in-memory pipe or a loopback socket. It misbehaves on demand.
+
+use super::{
+    peer::{decode_peer_message, msg_bytes_to_raw, read_chunk, write_chunk, PeerError},
+    transport::{BoxedTransport, Transport},
+    version::SupportedVersions,
+};
+use crate::{
+    constants::REMOTE_IDENTITY_JSON,
+    crypto::{
+        identity::Identity,
+        key::{CryptoKey, PublicKey},
+        nonce::Nonce,
+        peer_crypto::PeerCrypto,
+        pow::POW_SIZE,
+    },
+    msgs::{
+        ack::{AckStatus, NackInfo, NackMotive},
+        connection::{ConnectionMessage, NetworkVersion},
+        metadata::MetadataMessage,
+        peer::PeerMessage,
+        point::PointList,
+    },
+};
+use speedy::{Endianness, Readable, Writable};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncWriteExt, DuplexStream},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+/// Port announced in the mock connection message
+const MOCK_LISTENING_PORT: u16 = 9732;
+
+/// Handshake step a fault or a delay applies to, on the mock side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MockStage {
+    ConnectionMessage,
+    Metadata,
+    Ack,
+}
+
+/// How the mock corrupts the message it sends at a stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    /// Announces the full chunk length but sends half of it, then closes
+    Truncated,
+    /// Sends random bytes instead of the message
+    Garbage,
+}
+
+pub(crate) struct MockNode {
+    identity: Identity,
+    versions: Vec<NetworkVersion>,
+    metadata: MetadataMessage,
+    ack: AckStatus,
+    bad_pow: bool,
+    faults: Vec<(MockStage, Fault)>,
+    delays: Vec<(MockStage, Duration)>,
+}
+
+/// Encrypted session of a mock that completed its handshake
+pub(crate) struct MockSession {
+    stream: BoxedTransport,
+    peer_crypto: PeerCrypto,
+}
+
+impl MockNode {
+    /// A well behaved node on `chain_name`, announcing the versions we support
+    pub fn new(chain_name: &str) -> Self {
+        Self {
+            identity: Identity::from_json(REMOTE_IDENTITY_JSON).unwrap(),
+            versions: SupportedVersions::new(chain_name.to_string()).announced(),
+            metadata: MetadataMessage::default(),
+            ack: AckStatus::Ack,
+            bad_pow: false,
+            faults: vec![],
+            delays: vec![],
+        }
+    }
+
+    /// Announces `chain_name` instead, keeping the versions
+    pub fn with_chain_name(mut self, chain_name: &str) -> Self {
+        for version in &mut self.versions {
+            *version = NetworkVersion::new(
+                chain_name.to_string(),
+                version.distributed_db_version,
+                version.p2p_version,
+            );
+        }
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: MetadataMessage) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sends a zeroed proof-of-work stamp
+    pub fn with_bad_pow(mut self) -> Self {
+        self.bad_pow = true;
+        self
+    }
+
+    /// Answers the handshake with a Nack suggesting `points`
+    pub fn with_nack(mut self, motive: NackMotive, points: &[SocketAddr]) -> Self {
+        self.ack = AckStatus::NackV2(NackInfo::new(motive, PointList::from(points)));
+        self
+    }
+
+    pub fn with_fault(mut self, stage: MockStage, fault: Fault) -> Self {
+        self.faults.push((stage, fault));
+        self
+    }
+
+    /// Waits `duration` before sending the message of `stage`
+    pub fn with_delay(mut self, stage: MockStage, duration: Duration) -> Self {
+        self.delays.push((stage, duration));
+        self
+    }
+
+    /// Runs the mock over one end of an in-memory pipe, the other end is returned
+    pub fn spawn_duplex(
+        self,
+    ) -> (
+        DuplexStream,
+        JoinHandle<Result<Option<MockSession>, PeerError>>,
+    ) {
+        let (local, remote) = tokio::io::duplex(4 * u16::MAX as usize);
+        (remote, tokio::spawn(self.run(local)))
+    }
+
+    /// Runs the mock behind a loopback listener, for the first connection only
+    pub async fn spawn_listener(
+        self,
+    ) -> (
+        SocketAddr,
+        JoinHandle<Result<Option<MockSession>, PeerError>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.map_err(PeerError::Io)?;
+            self.run(stream).await
+        });
+        (addr, task)
+    }
+
+    /// Runs the responder side of the handshake over `stream`. Gives back the
+    /// session once acknowledged, `None` when the mock gave up as scripted.
+    pub async fn run<S: Transport + 'static>(
+        self,
+        stream: S,
+    ) -> Result<Option<MockSession>, PeerError> {
+        let mut stream: BoxedTransport = Box::new(stream);
+
+        let recv = read_chunk(&mut stream).await?;
+        let remote = ConnectionMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &recv)
+            .map_err(|reason| PeerError::MalformedMessage {
+                kind: "connection",
+                reason,
+            })?;
+        let stamp = if self.bad_pow {
+            vec![0; POW_SIZE]
+        } else {
+            self.identity.proof_of_work_stamp.as_ref().to_vec()
+        };
+        let sent = ConnectionMessage::new(
+            MOCK_LISTENING_PORT,
+            self.identity.public_key.as_ref().as_ref().to_vec(),
+            stamp,
+            Nonce::random().get_bytes().to_vec(),
+            self.versions.clone(),
+        )
+        .write_to_vec_with_ctx(Endianness::BigEndian)
+        .map_err(PeerError::SpeedyFailed)?;
+        if !self
+            .send(&mut stream, MockStage::ConnectionMessage, &sent)
+            .await?
+        {
+            return Ok(None);
+        }
+
+        let pk = PublicKey::from_bytes(&remote.public_key).map_err(PeerError::CryptoFailed)?;
+        let peer_crypto = PeerCrypto::build(
+            &self.identity.secret_key,
+            &pk,
+            msg_bytes_to_raw(&sent)?,
+            msg_bytes_to_raw(&recv)?,
+            true,
+        )
+        .map_err(PeerError::BuildPeerCryptoFailed)?;
+        let mut session = MockSession {
+            stream,
+            peer_crypto,
+        };
+
+        // Like our responder, the mock waits for each remote message before sending
+        // its own, so the remote never writes to a connection the mock dropped
+        session.recv_chunk().await?;
+        let metadata = self
+            .metadata
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .map_err(PeerError::SpeedyFailed)?;
+        if !self
+            .send_encrypted(&mut session, MockStage::Metadata, &metadata)
+            .await?
+        {
+            return Ok(None);
+        }
+
+        let remote_ack = session.recv_chunk().await?;
+        match AckStatus::read_from_buffer_with_ctx(Endianness::BigEndian, &remote_ack) {
+            Ok(AckStatus::Ack) => {}
+            Ok(AckStatus::NackV2(info)) => {
+                return Err(PeerError::Nack {
+                    motive: info.motive,
+                    potential_peers: info.potential_peers_to_connect.socket_addrs(),
+                })
+            }
+            Ok(AckStatus::NackV1) => {
+                return Err(PeerError::Nack {
+                    motive: NackMotive::NoMotive,
+                    potential_peers: vec![],
+                })
+            }
+            Err(reason) => {
+                return Err(PeerError::MalformedMessage {
+                    kind: "acknowledgement",
+                    reason,
+                })
+            }
+        }
+        let ack = self
+            .ack
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .map_err(PeerError::SpeedyFailed)?;
+        if !self
+            .send_encrypted(&mut session, MockStage::Ack, &ack)
+            .await?
+        {
+            return Ok(None);
+        }
+        if self.ack != AckStatus::Ack {
+            session.stream.shutdown().await.map_err(PeerError::Io)?;
+            return Ok(None);
+        }
+        Ok(Some(session))
+    }
+
+    async fn delay(&self, stage: MockStage) {
+        for (_, duration) in self.delays.iter().filter(|(s, _)| *s == stage) {
+            tokio::time::sleep(*duration).await;
+        }
+    }
+
+    fn fault(&self, stage: MockStage) -> Option<Fault> {
+        self.faults
+            .iter()
+            .find(|(s, _)| *s == stage)
+            .map(|(_, fault)| *fault)
+    }
+
+    /// Sends a clear chunk, returns false when a fault ended the handshake
+    async fn send(
+        &self,
+        stream: &mut BoxedTransport,
+        stage: MockStage,
+        data: &[u8],
+    ) -> Result<bool, PeerError> {
+        self.delay(stage).await;
+        match self.fault(stage) {
+            None => write_chunk(stream, data).await.map(|()| true),
+            Some(Fault::Garbage) => write_chunk(stream, &garbage(data.len()))
+                .await
+                .map(|()| true),
+            Some(Fault::Truncated) => {
+                let raw = msg_bytes_to_raw(data)?;
+                let half = 2 + data.len() / 2;
+                stream
+                    .write_all(&raw[..half])
+                    .await
+                    .map_err(PeerError::Io)?;
+                stream.shutdown().await.map_err(PeerError::Io)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Same as [`MockNode::send`] for chunks after the connection messages
+    async fn send_encrypted(
+        &self,
+        session: &mut MockSession,
+        stage: MockStage,
+        data: &[u8],
+    ) -> Result<bool, PeerError> {
+        let data = match self.fault(stage) {
+            // Replaced by random bytes, which fail authentication on the other side
+            Some(Fault::Garbage) => data.to_vec(),
+            _ => session
+                .peer_crypto
+                .encrypt(&data)
+                .map_err(PeerError::CryptoFailed)?,
+        };
+        self.send(&mut session.stream, stage, &data).await
+    }
+}
+
+impl MockSession {
+    pub async fn send_message(&mut self, message: &PeerMessage) -> Result<(), PeerError> {
+        let bytes = message
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .map_err(PeerError::SpeedyFailed)?;
+        let mut content = (bytes.len() as u32).to_be_bytes().to_vec();
+        content.extend(bytes);
+        let chunk = self
+            .peer_crypto
+            .encrypt(&content)
+            .map_err(PeerError::CryptoFailed)?;
+        write_chunk(&mut self.stream, &chunk).await
+    }
+
+    pub async fn recv_message(&mut self) -> Result<PeerMessage, PeerError> {
+        // Messages from our tests fit in a single chunk
+        let content = self.recv_chunk().await?;
+        let malformed = |reason: String| PeerError::MalformedMessage {
+            kind: "peer",
+            reason: speedy::Error::custom(reason),
+        };
+        if content.len() < 4 {
+            return Err(malformed(format!(
+                "Chunk of {} bytes is too short for a message size",
+                content.len()
+            )));
+        }
+        let (size, message) = content.split_at(4);
+        let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+        if size != message.len() {
+            return Err(malformed(format!(
+                "Message size of {} bytes, {} bytes received",
+                size,
+                message.len()
+            )));
+        }
+        decode_peer_message(message, None)
+    }
+
+    /// Sends `data` as a chunk, without encrypting it
+    pub async fn send_raw_chunk(&mut self, data: &[u8]) -> Result<(), PeerError> {
+        write_chunk(&mut self.stream, data).await
+    }
+
+    async fn recv_chunk(&mut self) -> Result<Vec<u8>, PeerError> {
+        let chunk = read_chunk(&mut self.stream).await?;
+        self.peer_crypto
+            .decrypt(&chunk)
+            .map_err(PeerError::CryptoFailed)
+    }
+}
+
+fn garbage(len: usize) -> Vec<u8> {
+    sodiumoxide::randombytes::randombytes(len.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::DEFAUL_IDENTITY_JSON,
+        crypto::key::CryptoError,
+        p2p::{
+            config::PeerConfig,
+            handle::TerminationReason,
+            peer::{Peer, TimeoutStage},
+        },
+    };
+
+    const CHAIN_NAME: &str = "TEZOS_MAINNET";
+
+    fn initiator<S: Transport + 'static>(stream: S, config: PeerConfig) -> Peer {
+        let identity = Identity::from_json(DEFAUL_IDENTITY_JSON).unwrap();
+        let addr = SocketAddr::from(([127, 0, 0, 1], MOCK_LISTENING_PORT));
+        Peer::from_stream(stream, addr, identity, config)
+    }
+
+    /// Handshakes with `mock` over a pipe, returns both outcomes
+    async fn handshake_with(
+        mock: MockNode,
+        config: PeerConfig,
+    ) -> (
+        Result<Peer, PeerError>,
+        Result<Option<MockSession>, PeerError>,
+    ) {
+        let (stream, mock) = mock.spawn_duplex();
+        let mut peer = initiator(stream, config);
+        let result = peer.handshake().await.map(|()| peer);
+        (result, mock.await.unwrap())
+    }
+
+    fn config() -> PeerConfig {
+        PeerConfig::new(CHAIN_NAME.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_handshake_with_mock() {
+        let mock = MockNode::new(CHAIN_NAME).with_metadata(MetadataMessage::new(true, false));
+        let (peer, session) = handshake_with(mock, config()).await;
+        let peer = peer.unwrap();
+        assert!(session.unwrap().is_some());
+        let info = peer.info().unwrap();
+        assert_eq!(info.peer_id.to_string(), "idtCFAZinPMECvZCCcbUTX5HwSxn3C");
+        assert_eq!(info.version.chain_name, CHAIN_NAME);
+        assert!(info.metadata.disable_mempool);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_with_mock_over_loopback() {
+        let (addr, mock) = MockNode::new(CHAIN_NAME).spawn_listener().await;
+        let identity = Identity::from_json(DEFAUL_IDENTITY_JSON).unwrap();
+        let mut peer = Peer::connect(addr, identity, config()).await.unwrap();
+        peer.handshake().await.unwrap();
+        assert!(mock.await.unwrap().unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_mock_wrong_chain_name() {
+        let mock = MockNode::new(CHAIN_NAME).with_chain_name("TEZOS_GHOSTNET_2022-01-25T15:00:00Z");
+        let (peer, session) = handshake_with(mock, config()).await;
+        assert!(matches!(peer, Err(PeerError::NoCommonVersion { .. })));
+        assert!(matches!(
+            session,
+            Err(PeerError::Nack {
+                motive: NackMotive::UnknownChainName,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mock_bad_pow() {
+        let (peer, session) =
+            handshake_with(MockNode::new(CHAIN_NAME).with_bad_pow(), config()).await;
+        assert!(matches!(
+            peer,
+            Err(PeerError::InsufficientProofOfWork { .. })
+        ));
+        assert!(matches!(
+            session,
+            Err(PeerError::Nack {
+                motive: NackMotive::NoMotive,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mock_nack() {
+        let point = SocketAddr::from(([192, 0, 2, 1], 9732));
+        let mock = MockNode::new(CHAIN_NAME).with_nack(NackMotive::TooManyConnections, &[point]);
+        let (peer, session) = handshake_with(mock, config()).await;
+        match peer {
+            Err(PeerError::Nack {
+                motive,
+                potential_peers,
+            }) => {
+                assert_eq!(motive, NackMotive::TooManyConnections);
+                assert_eq!(potential_peers, vec![point]);
+            }
+            other => panic!("Expected a Nack, got {:?}", other.err()),
+        }
+        assert!(session.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mock_truncated_frames() {
+        for stage in [
+            MockStage::ConnectionMessage,
+            MockStage::Metadata,
+            MockStage::Ack,
+        ] {
+            let mock = MockNode::new(CHAIN_NAME).with_fault(stage, Fault::Truncated);
+            let (peer, session) = handshake_with(mock, config()).await;
+            assert!(
+                matches!(&peer, Err(PeerError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof),
+                "{:?}: {:?}",
+                stage,
+                peer.err()
+            );
+            assert!(session.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_garbage_connection_message() {
+        let mock =
+            MockNode::new(CHAIN_NAME).with_fault(MockStage::ConnectionMessage, Fault::Garbage);
+        let (peer, _) = handshake_with(mock, config()).await;
+        // Random bytes fail to decode, or carry a key with a weak proof-of-work and
+        // session keys the mock does not share, so it drops the connection
+        assert!(matches!(
+            peer,
+            Err(PeerError::MalformedMessage {
+                kind: "connection",
+                ..
+            } | PeerError::CryptoFailed(_)
+                | PeerError::InsufficientProofOfWork { .. }
+                | PeerError::Io(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mock_garbage_ciphertext() {
+        for stage in [MockStage::Metadata, MockStage::Ack] {
+            let mock = MockNode::new(CHAIN_NAME).with_fault(stage, Fault::Garbage);
+            let (peer, _) = handshake_with(mock, config()).await;
+            assert!(
+                matches!(
+                    peer,
+                    Err(PeerError::CryptoFailed(CryptoError::FailedToDecrypt))
+                ),
+                "{:?}",
+                stage
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_delays() {
+        for (stage, expected) in [
+            (
+                MockStage::ConnectionMessage,
+                TimeoutStage::ConnectionMessage,
+            ),
+            (MockStage::Metadata, TimeoutStage::Metadata),
+            (MockStage::Ack, TimeoutStage::Ack),
+        ] {
+            let mock = MockNode::new(CHAIN_NAME).with_delay(stage, Duration::from_millis(500));
+            let config = config().with_handshake_timeout(Duration::from_millis(100));
+            let (stream, _mock) = mock.spawn_duplex();
+            let mut peer = initiator(stream, config);
+            match peer.handshake().await {
+                Err(PeerError::Timeout { stage }) => assert_eq!(stage, expected),
+                other => panic!("Expected a timeout, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_rejects_malformed_message_size() {
+        let (peer, session) = handshake_with(MockNode::new(CHAIN_NAME), config()).await;
+        let mut peer = peer.unwrap();
+        let mut session = session.unwrap().unwrap();
+
+        for content in [vec![0, 0], vec![0, 0, 0, 9, 0, 1]] {
+            peer.send_msg(content, true).await.unwrap();
+            assert!(matches!(
+                session.recv_message().await,
+                Err(PeerError::MalformedMessage { kind: "peer", .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_exchanges_messages_after_handshake() {
+        let (peer, session) = handshake_with(MockNode::new(CHAIN_NAME), config()).await;
+        let mut session = session.unwrap().unwrap();
+        let mut handle = peer.unwrap().spawn().unwrap();
+
+        handle.sender().send(PeerMessage::Bootstrap).await.unwrap();
+        assert_eq!(
+            session.recv_message().await.unwrap(),
+            PeerMessage::Bootstrap
+        );
+        session.send_message(&PeerMessage::Bootstrap).await.unwrap();
+        assert_eq!(handle.recv().await, Some(PeerMessage::Bootstrap));
+
+        session.send_raw_chunk(&garbage(64)).await.unwrap();
+        assert!(matches!(
+            handle.terminated().await,
+            TerminationReason::Crypto(CryptoError::FailedToDecrypt)
+        ));
+    }
+}
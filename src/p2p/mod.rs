@@ -1,2 +1,11 @@
+pub mod chunk;
+pub mod config;
 pub mod dns;
+pub mod handle;
+pub mod listener;
+#[cfg(test)]
+pub(crate) mod mock;
+pub mod network;
 pub mod peer;
+pub mod transport;
+pub mod version;
@@ -0,0 +1,300 @@
+use super::{
+    config::PeerConfig,
+    dns,
+    version::{DEFAULT_DISTRIBUTED_DB_VERSIONS, DEFAULT_P2P_VERSIONS},
+};
+use crate::{
+    constants::BOOTSTRAP_DEFAULT_PORT,
+    crypto::{
+        blake2b::{self, Blake2bError},
+        hash::{BlockHash, ChainId, ProtocolHash},
+    },
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    io,
+    net::SocketAddr,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NetworkConfigError {
+    #[error("I/O error reading {path}: {reason}")]
+    IoError { path: PathBuf, reason: io::Error },
+
+    #[error("Invalid network description, reason: {reason}")]
+    NetworkSerdeError { reason: serde_json::Error },
+}
+
+/// First block of a chain, the chain id is derived from its hash
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Genesis {
+    pub block: BlockHash,
+    pub protocol: ProtocolHash,
+    /// RFC 3339 timestamp, e.g. `2018-06-30T16:07:32Z`
+    pub timestamp: String,
+}
+
+/// Everything that identifies a Tezos network, so the chain name,
+/// bootstrap peers and versions we use always belong together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConfig {
+    pub chain_name: String,
+    pub sandboxed_chain_name: String,
+    pub genesis: Genesis,
+    /// Bootstrap nodes as `host` or `host:port`
+    pub default_bootstrap_peers: Vec<String>,
+    pub distributed_db_versions: RangeInclusive<u16>,
+    pub p2p_versions: RangeInclusive<u16>,
+    /// Protocol parameters of the genesis block, kept as is
+    pub genesis_parameters: Option<Value>,
+}
+
+/// The octez `network` object, as found in network files and `config.json`
+#[derive(Deserialize)]
+struct NetworkJson {
+    genesis: Genesis,
+    chain_name: String,
+    sandboxed_chain_name: String,
+    #[serde(default)]
+    default_bootstrap_peers: Vec<String>,
+    genesis_parameters: Option<Value>,
+}
+
+impl NetworkConfig {
+    pub fn mainnet() -> Self {
+        Self::custom(
+            "TEZOS_MAINNET".to_string(),
+            Genesis {
+                block: "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2"
+                    .parse()
+                    .expect("valid mainnet genesis block"),
+                protocol: "Ps9mPmXaRzmzk35gbAYNCAw6UXdE2qoABTHbN2oEEc1qM7CwT9P"
+                    .parse()
+                    .expect("valid mainnet genesis protocol"),
+                timestamp: "2018-06-30T16:07:32Z".to_string(),
+            },
+        )
+        .with_sandboxed_chain_name("SANDBOXED_TEZOS_MAINNET".to_string())
+        .with_bootstrap_peers(&[
+            "boot.tzboot.net",
+            "boot.tzbeta.net",
+            "boot.mainnet.oxheadhosted.com",
+        ])
+    }
+
+    pub fn ghostnet() -> Self {
+        Self::custom(
+            "TEZOS_ITHACANET_2022-01-25T15:00:00Z".to_string(),
+            Genesis {
+                block: "BLockGenesisGenesisGenesisGenesisGenesis1db77eJNeJ9"
+                    .parse()
+                    .expect("valid ghostnet genesis block"),
+                protocol: "Ps9mPmXaRzmzk35gbAYNCAw6UXdE2qoABTHbN2oEEc1qM7CwT9P"
+                    .parse()
+                    .expect("valid ghostnet genesis protocol"),
+                timestamp: "2022-01-25T15:00:00Z".to_string(),
+            },
+        )
+        .with_bootstrap_peers(&[
+            "ghostnet.teztnets.com",
+            "ghostnet.tzinit.org",
+            "ghostnet.tzboot.net",
+            "ghostnet.boot.ecadinfra.com",
+            "ghostnet.stakenow.de:9733",
+        ])
+    }
+
+    /// Local network started by `octez-node --sandbox`, it has no bootstrap peers
+    pub fn sandbox() -> Self {
+        Self::custom(
+            "TEZOS".to_string(),
+            Genesis {
+                block: "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2"
+                    .parse()
+                    .expect("valid sandbox genesis block"),
+                protocol: "ProtoGenesisGenesisGenesisGenesisGenesisGenesk612im"
+                    .parse()
+                    .expect("valid sandbox genesis protocol"),
+                timestamp: "2018-06-30T16:07:32Z".to_string(),
+            },
+        )
+    }
+
+    /// Network without bootstrap peers, using the default versions
+    pub fn custom(chain_name: String, genesis: Genesis) -> Self {
+        Self {
+            chain_name,
+            sandboxed_chain_name: "SANDBOXED_TEZOS".to_string(),
+            genesis,
+            default_bootstrap_peers: vec![],
+            distributed_db_versions: DEFAULT_DISTRIBUTED_DB_VERSIONS,
+            p2p_versions: DEFAULT_P2P_VERSIONS,
+            genesis_parameters: None,
+        }
+    }
+
+    /// Preset named like octez `--network` aliases, case insensitive
+    pub fn from_alias(alias: &str) -> Option<Self> {
+        match alias.to_lowercase().as_str() {
+            "mainnet" => Some(Self::mainnet()),
+            "ghostnet" => Some(Self::ghostnet()),
+            "sandbox" => Some(Self::sandbox()),
+            _ => None,
+        }
+    }
+
+    /// Parses an octez `network` object, as used to describe private networks.
+    pub fn from_json(json: &str) -> Result<Self, NetworkConfigError> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| NetworkConfigError::NetworkSerdeError { reason: e })?;
+        Self::from_json_value(value)
+    }
+
+    pub fn from_json_value(value: Value) -> Result<Self, NetworkConfigError> {
+        let network: NetworkJson = serde_json::from_value(value)
+            .map_err(|e| NetworkConfigError::NetworkSerdeError { reason: e })?;
+
+        let mut config = Self::custom(network.chain_name, network.genesis)
+            .with_sandboxed_chain_name(network.sandboxed_chain_name);
+        config.default_bootstrap_peers = network.default_bootstrap_peers;
+        config.genesis_parameters = network.genesis_parameters;
+        Ok(config)
+    }
+
+    pub fn from_json_file(path: &Path) -> Result<Self, NetworkConfigError> {
+        let json = std::fs::read_to_string(path).map_err(|e| NetworkConfigError::IoError {
+            path: path.to_path_buf(),
+            reason: e,
+        })?;
+        Self::from_json(&json)
+    }
+
+    pub fn with_chain_name(mut self, chain_name: String) -> Self {
+        self.chain_name = chain_name;
+        self
+    }
+
+    pub fn with_sandboxed_chain_name(mut self, sandboxed_chain_name: String) -> Self {
+        self.sandboxed_chain_name = sandboxed_chain_name;
+        self
+    }
+
+    pub fn with_bootstrap_peers(mut self, peers: &[&str]) -> Self {
+        self.default_bootstrap_peers = peers.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// The chain id is the first 4 bytes of the blake2b-256 hash of the genesis block hash
+    pub fn chain_id(&self) -> Result<ChainId, Blake2bError> {
+        let digest = blake2b::digest_256(self.genesis.block.as_ref())?;
+        let mut chain_id = [0u8; ChainId::SIZE];
+        chain_id.copy_from_slice(&digest[..ChainId::SIZE]);
+        Ok(ChainId::from(chain_id))
+    }
+
+    /// Resolves the bootstrap peers, the default port is used when one has none
+    pub fn bootstrap_addrs(&self) -> Vec<SocketAddr> {
+        dns::lookup_points(&self.default_bootstrap_peers, BOOTSTRAP_DEFAULT_PORT)
+    }
+
+    /// Handshake settings negotiating this network chain name and versions,
+    /// messages about another chain are then rejected
+    pub fn peer_config(&self) -> PeerConfig {
+        let config = PeerConfig::new(self.chain_name.clone())
+            .with_distributed_db_versions(self.distributed_db_versions.clone())
+            .with_p2p_versions(self.p2p_versions.clone());
+        match self.chain_id() {
+            Ok(chain_id) => config.with_chain_id(chain_id),
+            Err(_) => config,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mainnet_chain_id() {
+        let chain_id = NetworkConfig::mainnet().chain_id().unwrap();
+        assert_eq!(chain_id.to_string(), "NetXdQprcVkpaWU");
+    }
+
+    #[test]
+    fn test_ghostnet_chain_id() {
+        let chain_id = NetworkConfig::ghostnet().chain_id().unwrap();
+        assert_eq!(chain_id.to_string(), "NetXnHfVqm9iesp");
+    }
+
+    #[test]
+    fn test_sandbox_has_no_bootstrap_peers() {
+        let sandbox = NetworkConfig::sandbox();
+        assert_eq!(sandbox.chain_name, "TEZOS");
+        assert!(sandbox.default_bootstrap_peers.is_empty());
+        assert!(sandbox.bootstrap_addrs().is_empty());
+    }
+
+    #[test]
+    fn test_peer_config_uses_network_chain_name() {
+        let config = NetworkConfig::ghostnet().peer_config();
+        assert_eq!(
+            config.versions.chain_name,
+            "TEZOS_ITHACANET_2022-01-25T15:00:00Z"
+        );
+        assert_eq!(
+            config.chain_id.map(|id| id.to_string()),
+            Some("NetXnHfVqm9iesp".to_string())
+        );
+    }
+
+    const GHOSTNET_JSON: &str = r#"{
+  "genesis": {
+    "timestamp": "2022-01-25T15:00:00Z",
+    "block": "BLockGenesisGenesisGenesisGenesisGenesis1db77eJNeJ9",
+    "protocol": "Ps9mPmXaRzmzk35gbAYNCAw6UXdE2qoABTHbN2oEEc1qM7CwT9P"
+  },
+  "genesis_parameters": {
+    "values": {
+      "genesis_pubkey": "edpkuYLienS3Xdt5c1vfRX1ibMxQuvfM67ByhJ9nmRYYKGAAoTq1UC"
+    }
+  },
+  "chain_name": "TEZOS_ITHACANET_2022-01-25T15:00:00Z",
+  "sandboxed_chain_name": "SANDBOXED_TEZOS",
+  "default_bootstrap_peers": [ "ghostnet.teztnets.com", "ghostnet.stakenow.de:9733" ]
+}"#;
+
+    #[test]
+    fn test_network_from_json() {
+        let network = NetworkConfig::from_json(GHOSTNET_JSON).unwrap();
+        assert_eq!(network.chain_name, "TEZOS_ITHACANET_2022-01-25T15:00:00Z");
+        assert_eq!(network.sandboxed_chain_name, "SANDBOXED_TEZOS");
+        assert_eq!(network.genesis, NetworkConfig::ghostnet().genesis);
+        assert_eq!(network.chain_id().unwrap().to_string(), "NetXnHfVqm9iesp");
+        assert_eq!(
+            network.default_bootstrap_peers,
+            vec!["ghostnet.teztnets.com", "ghostnet.stakenow.de:9733"]
+        );
+        assert!(network.genesis_parameters.is_some());
+    }
+
+    #[test]
+    fn test_network_from_json_invalid_genesis() {
+        let json = GHOSTNET_JSON.replace("1db77eJNeJ9", "1db77eJNeJ8");
+        assert!(matches!(
+            NetworkConfig::from_json(&json),
+            Err(NetworkConfigError::NetworkSerdeError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_network_from_json_file_missing() {
+        assert!(matches!(
+            NetworkConfig::from_json_file(Path::new("/nonexistent/network.json")),
+            Err(NetworkConfigError::IoError { .. })
+        ));
+    }
+}
@@ -1,34 +1,66 @@
+use super::{
+    chunk::{self, ChunkError, MessageReassembler, MAX_CHUNK_SIZE},
+    config::PeerConfig,
+    handle::PeerHandle,
+    transport::{BoxedTransport, Transport, TransportReader, TransportWriter},
+    version::{display_versions, SupportedVersions},
+};
 use crate::{
     crypto::{
         blake2b::Blake2bError,
+        hash::ChainId,
         identity::Identity,
+        identity::PublicKeyError,
         key::{CryptoError, CryptoKey, PublicKey},
         nonce::Nonce,
-        peer_crypto::PeerCrypto,
+        peer_crypto::{PeerCrypto, PeerCryptoReader, PeerCryptoWriter},
+        peer_id::PeerId,
+        pow::{PowError, ProofOfWork},
     },
     msgs::{
         self,
-        ack::AckStatus,
+        ack::{AckStatus, NackInfo, NackMotive},
         connection::{ConnectionMessage, NetworkVersion},
         metadata::MetadataMessage,
+        peer::PeerMessage,
+        point::PointList,
     },
 };
 use speedy::{Endianness, Error, Readable, Writable};
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    fmt::{self, Debug},
+    future::Future,
+    time::Duration,
+};
 use thiserror::Error;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
-    sync::Mutex,
 };
 
 pub struct Peer {
     socket: std::net::SocketAddr,
     state: PeerState,
-    stream: Arc<Mutex<TcpStream>>,
+    stream: BoxedTransport,
     identity: Identity,
     peer_crypto: Option<PeerCrypto>,
-    chain_name: String,
+    config: PeerConfig,
+    incoming: bool,
+    listening_port: u16,
+    info: Option<PeerInfo>,
+    reassembler: MessageReassembler,
+    /// Set once a read was abandoned midway, the stream is out of step with the chunks
+    idle_timed_out: bool,
+}
+
+/// What we learned about the remote node during the handshake
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub address: std::net::SocketAddr,
+    pub peer_id: PeerId,
+    pub public_key: PublicKey,
+    pub version: NetworkVersion,
+    pub metadata: MetadataMessage,
 }
 
 #[derive(Debug, Error)]
@@ -37,16 +69,104 @@ pub enum PeerError {
     Io(std::io::Error),
     #[error("Connection failed")]
     ConnectionFailed,
-    #[error("Ack failed")]
-    AckFailed,
+    #[error("Peer refused the connection: {motive}")]
+    Nack {
+        motive: NackMotive,
+        /// Points the remote suggested we try instead
+        potential_peers: Vec<std::net::SocketAddr>,
+    },
     #[error("Speedy failed: {0}")]
     SpeedyFailed(Error),
+    #[error("Malformed {kind} message: {reason}")]
+    MalformedMessage { kind: &'static str, reason: Error },
     #[error("Blake2b error: {0}")]
     BuildPeerCryptoFailed(Blake2bError),
     #[error("Peer crypto not initialized")]
     PeerCryptoNotInitialized,
     #[error("Crypto failed: {0}")]
     CryptoFailed(CryptoError),
+    #[error("Invalid remote peer id: {0}")]
+    InvalidPeerId(PublicKeyError),
+    #[error(
+        "No common network version, we support {local}, remote offered {}",
+        display_versions(.remote)
+    )]
+    NoCommonVersion {
+        local: SupportedVersions,
+        remote: Vec<NetworkVersion>,
+    },
+    #[error("We refused the connection: {motive}")]
+    Refused { motive: NackMotive },
+    #[error("Proof-of-work below the expected difficulty {target}: {reason}")]
+    InsufficientProofOfWork { target: f64, reason: PowError },
+    #[error("Chunk error: {0}")]
+    Chunk(ChunkError),
+    #[error("Message for chain {found}, we follow {expected}")]
+    UnexpectedChainId { expected: ChainId, found: ChainId },
+    #[error("Timed out during {stage}")]
+    Timeout { stage: TimeoutStage },
+    #[error("Connection unusable after a read timed out")]
+    Desynchronized,
+}
+
+/// Connection stage that took longer than its [`Timeouts`](super::config::Timeouts) entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutStage {
+    Connect,
+    ConnectionMessage,
+    Metadata,
+    Ack,
+    Idle,
+}
+
+impl fmt::Display for TimeoutStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeoutStage::Connect => "connect",
+            TimeoutStage::ConnectionMessage => "connection message exchange",
+            TimeoutStage::Metadata => "metadata exchange",
+            TimeoutStage::Ack => "acknowledgement exchange",
+            TimeoutStage::Idle => "idle read",
+        })
+    }
+}
+
+/// Runs `future`, failing with [`PeerError::Timeout`] once `duration` elapsed
+async fn with_timeout<T, F>(
+    duration: Duration,
+    stage: TimeoutStage,
+    future: F,
+) -> Result<T, PeerError>
+where
+    F: Future<Output = Result<T, PeerError>>,
+{
+    tokio::time::timeout(duration, future)
+        .await
+        .unwrap_or(Err(PeerError::Timeout { stage }))
+}
+
+/// Same as [`with_timeout`], without limit when `duration` is unset
+async fn with_optional_timeout<T, F>(
+    duration: Option<Duration>,
+    stage: TimeoutStage,
+    future: F,
+) -> Result<T, PeerError>
+where
+    F: Future<Output = Result<T, PeerError>>,
+{
+    match duration {
+        Some(duration) => with_timeout(duration, stage, future).await,
+        None => future.await,
+    }
+}
+
+fn is_idle_timeout(e: &PeerError) -> bool {
+    matches!(
+        e,
+        PeerError::Timeout {
+            stage: TimeoutStage::Idle
+        }
+    )
 }
 
 enum PeerState {
@@ -61,20 +181,116 @@ impl Peer {
     pub async fn connect(
         socket: std::net::SocketAddr,
         identity: Identity,
-        chain_name: String,
+        config: PeerConfig,
     ) -> Result<Self, PeerError> {
         let addr = format!("{}:{}", socket.ip(), socket.port());
-        let stream_raw = TcpStream::connect(addr).await.map_err(PeerError::Io)?;
-        let stream = Arc::new(Mutex::new(stream_raw));
+        let stream = with_timeout(config.timeouts.connect, TimeoutStage::Connect, async {
+            TcpStream::connect(addr).await.map_err(PeerError::Io)
+        })
+        .await?;
+        Ok(Self::from_stream(stream, socket, identity, config))
+    }
+
+    /// Wraps an already open connection to `socket`, we are the initiator
+    /// and run the initiator side of the handshake. We announce the
+    /// [`PeerConfig::listening_port`].
+    pub fn from_stream<S: Transport + 'static>(
+        stream: S,
+        socket: std::net::SocketAddr,
+        identity: Identity,
+        config: PeerConfig,
+    ) -> Self {
+        Self::new(
+            Box::new(stream),
+            socket,
+            config.listening_port,
+            false,
+            identity,
+            config,
+        )
+    }
+
+    /// Wraps a connection accepted by our listener, the remote peer
+    /// is the initiator so we run the responder side of the handshake.
+    pub fn accept(
+        stream: TcpStream,
+        identity: Identity,
+        config: PeerConfig,
+    ) -> Result<Self, PeerError> {
+        let socket = stream.peer_addr().map_err(PeerError::Io)?;
+        let listening_port = stream.local_addr().map_err(PeerError::Io)?.port();
+        Ok(Self::accept_stream(
+            stream,
+            socket,
+            listening_port,
+            identity,
+            config,
+        ))
+    }
+
+    /// Wraps a connection from `socket` accepted by our listener on `listening_port`,
+    /// we run the responder side of the handshake.
+    pub fn accept_stream<S: Transport + 'static>(
+        stream: S,
+        socket: std::net::SocketAddr,
+        listening_port: u16,
+        identity: Identity,
+        config: PeerConfig,
+    ) -> Self {
+        Self::new(
+            Box::new(stream),
+            socket,
+            listening_port,
+            true,
+            identity,
+            config,
+        )
+    }
 
-        Ok(Peer {
+    fn new(
+        stream: BoxedTransport,
+        socket: std::net::SocketAddr,
+        listening_port: u16,
+        incoming: bool,
+        identity: Identity,
+        config: PeerConfig,
+    ) -> Self {
+        let reassembler = MessageReassembler::new(config.max_message_size);
+        Peer {
             socket,
             stream,
             state: PeerState::Connecting,
             identity,
             peer_crypto: None,
-            chain_name,
-        })
+            config,
+            incoming,
+            listening_port,
+            info: None,
+            reassembler,
+            idle_timed_out: false,
+        }
+    }
+
+    pub fn socket(&self) -> std::net::SocketAddr {
+        self.socket
+    }
+
+    pub fn is_incoming(&self) -> bool {
+        self.incoming
+    }
+
+    /// Remote node information, available once the handshake succeeded
+    pub fn info(&self) -> Option<&PeerInfo> {
+        self.info.as_ref()
+    }
+
+    pub fn remote_peer_id(&self) -> Option<&PeerId> {
+        self.info.as_ref().map(|info| &info.peer_id)
+    }
+
+    /// Version agreed with the remote node during the handshake
+    pub fn version(&self) -> Option<&NetworkVersion> {
+        self.info.as_ref().map(|info| &info.version)
     }
 
     pub fn peer_crypto_mut(&mut self) -> &mut Option<PeerCrypto> {
@@ -82,91 +298,237 @@ impl Peer {
     }
 
     pub async fn desconnect(&mut self) -> Result<(), PeerError> {
-        let mut stream = self.stream.lock().await;
-        stream.flush().await.map_err(PeerError::Io)?;
-        stream.shutdown().await.map_err(PeerError::Io)?;
+        self.stream.flush().await.map_err(PeerError::Io)?;
+        self.stream.shutdown().await.map_err(PeerError::Io)?;
         self.state = PeerState::Disconnected;
         Ok(())
     }
 
     pub async fn handshake(&mut self) -> Result<(), PeerError> {
+        self.handshake_with(None).await
+    }
+
+    /// Runs the handshake but answers with a Nack carrying `motive`,
+    /// e.g. when a listener already has too many connections.
+    pub async fn refuse(&mut self, motive: NackMotive) -> Result<(), PeerError> {
+        self.handshake_with(Some(motive)).await
+    }
+
+    /// Exchanges the connection and metadata messages, then accepts the remote with an
+    /// Ack or rejects it with a Nack: on `refusal`, a proof-of-work below our expected one
+    /// or without a common version.
+    async fn handshake_with(&mut self, refusal: Option<NackMotive>) -> Result<(), PeerError> {
         let connection_msg = ConnectionMessage::new(
-            self.socket.port(),
+            self.listening_port,
             self.identity.public_key.as_ref().as_ref().to_vec(),
             self.identity.proof_of_work_stamp.as_ref().to_vec(),
             Nonce::random().get_bytes().to_vec(),
-            NetworkVersion::new(self.chain_name.clone(), 2, 1),
+            self.config.versions.announced(),
         );
 
         let sent = connection_msg
             .write_to_vec_with_ctx(Endianness::BigEndian)
-            .map_err(|e| PeerError::SpeedyFailed(e))?;
+            .map_err(PeerError::SpeedyFailed)?;
 
-        // As we're a outcoming connection, we send the connection message first
-        self.send_msg(sent.to_vec(), false).await?;
+        // The initiator sends its connection message first, the responder
+        // waits for the remote one before answering with ours
+        let timeouts = self.config.timeouts;
+        let recv = with_timeout(
+            timeouts.connection_message,
+            TimeoutStage::ConnectionMessage,
+            async {
+                if self.incoming {
+                    let recv = self.recv_msg(false).await?;
+                    self.send_msg(sent.to_vec(), false).await?;
+                    Ok(recv)
+                } else {
+                    self.send_msg(sent.to_vec(), false).await?;
+                    self.recv_msg(false).await
+                }
+            },
+        )
+        .await?;
         println!("Sent connection message: {:?}", connection_msg);
 
-        // Receive the connection message
-        let recv = self.recv_msg(false).await?;
         let cm_msg = msgs::connection::ConnectionMessage::read_from_buffer_with_ctx(
             Endianness::BigEndian,
             &recv,
-            // binary_chunk.content(),
         )
-        .map_err(|e| PeerError::SpeedyFailed(e))?;
+        .map_err(|reason| PeerError::MalformedMessage {
+            kind: "connection",
+            reason,
+        })?;
         println!("Received connection message: {:?}", cm_msg);
 
-        // Encryption everything after this point
+        // A weak proof-of-work is refused with a Nack once the metadata are exchanged
         let pk = PublicKey::from_bytes(&cm_msg.public_key).map_err(PeerError::CryptoFailed)?;
+        let pow = ProofOfWork::from_bytes(&cm_msg.proof_of_work_stamp)
+            .map_err(PeerError::CryptoFailed)?
+            .check(&pk, self.config.expected_pow);
+        let remote_peer_id = PeerId::from_public_key(&pk).map_err(PeerError::InvalidPeerId)?;
+        println!("Remote peer id: {}", remote_peer_id);
+
+        // Encryption everything after this point
         *self.peer_crypto_mut() = Some(
             PeerCrypto::build(
                 &self.identity.secret_key,
                 &pk,
-                msg_bytes_to_raw(&sent),
-                msg_bytes_to_raw(&recv),
-                false,
+                msg_bytes_to_raw(&sent)?,
+                msg_bytes_to_raw(&recv)?,
+                self.incoming,
             )
-            .map_err(|e| PeerError::BuildPeerCryptoFailed(e))?,
+            .map_err(PeerError::BuildPeerCryptoFailed)?,
         );
 
         // Send metadata
-        let meta_msg = MetadataMessage::new(false, false);
+        let meta_msg = self.config.metadata;
         let meta_msg_vec = meta_msg
-            .write_to_vec()
-            .map_err(|e| PeerError::SpeedyFailed(e))?;
-        self.send_msg(meta_msg_vec, true).await?;
-        println!("Sent metadata message: {:?}", meta_msg);
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .map_err(PeerError::SpeedyFailed)?;
+        let meta_msg_recv = with_timeout(timeouts.metadata, TimeoutStage::Metadata, async {
+            self.send_msg(meta_msg_vec, true).await?;
+            println!("Sent metadata message: {:?}", meta_msg);
+
+            // Receive metadata
+            self.recv_msg(true).await
+        })
+        .await?;
+        let remote_meta_msg =
+            MetadataMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &meta_msg_recv)
+                .map_err(|reason| PeerError::MalformedMessage {
+                    kind: "metadata",
+                    reason,
+                })?;
+        println!("Received metadata message: {:?}", remote_meta_msg);
 
-        // Receive metadata
-        let meta_msg_recv = self.recv_msg(true).await?;
-        MetadataMessage::read_from_buffer(&meta_msg_recv)
-            .expect("Failed to parse metadata message");
-        println!("Received metadata message: {:?}", meta_msg);
+        // Now that we know everything about the remote, decide whether we accept it
+        let verdict = match (refusal, pow, self.config.versions.check(&cm_msg.versions)) {
+            (Some(motive), _, _) => Err((motive, PeerError::Refused { motive })),
+            // octez closes right after the connection message on `Not_enough_proof_of_work`,
+            // we say why with the only motive that fits
+            (None, Err(reason), _) => Err((
+                NackMotive::NoMotive,
+                PeerError::InsufficientProofOfWork {
+                    target: self.config.expected_pow,
+                    reason,
+                },
+            )),
+            (None, Ok(()), Err(motive)) => Err((
+                motive,
+                PeerError::NoCommonVersion {
+                    local: self.config.versions.clone(),
+                    remote: cm_msg.versions.clone(),
+                },
+            )),
+            (None, Ok(()), Ok(version)) => Ok(version),
+        };
+        let version = match verdict {
+            Ok(version) => version,
+            Err((motive, error)) => {
+                // Peers announcing the first p2p version only understand the bare Nack
+                let nack_msg = if cm_msg.versions.iter().all(|v| v.p2p_version == 0) {
+                    AckStatus::NackV1
+                } else {
+                    AckStatus::NackV2(NackInfo::new(
+                        motive,
+                        PointList::from(self.config.known_points.as_slice()),
+                    ))
+                };
+                let nack_msg_vec = nack_msg
+                    .write_to_vec_with_ctx(Endianness::BigEndian)
+                    .map_err(PeerError::SpeedyFailed)?;
+                with_timeout(timeouts.ack, TimeoutStage::Ack, async {
+                    self.send_msg(nack_msg_vec, true).await?;
+                    println!("Sent acknowledgement message: {:?}", nack_msg);
+                    self.desconnect().await
+                })
+                .await?;
+                return Err(error);
+            }
+        };
+        println!("Negotiated version: {}", version);
 
         // Send ack
         let ack_msg = AckStatus::Ack;
-        self.send_msg(
-            ack_msg.write_to_vec().map_err(PeerError::SpeedyFailed)?,
-            true,
-        )
-        .await?;
-        println!("Sent acknowledgement message: {:?}", ack_msg);
+        let ack_msg_vec = ack_msg
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .map_err(PeerError::SpeedyFailed)?;
+        let ack_msg_recv = with_timeout(timeouts.ack, TimeoutStage::Ack, async {
+            self.send_msg(ack_msg_vec, true).await?;
+            println!("Sent acknowledgement message: {:?}", ack_msg);
 
-        // Receive ack
-        let ack_msg_recv = self.recv_msg(true).await?;
-        let ack_msg =
-            AckStatus::read_from_buffer(&ack_msg_recv).expect("Failed to parse ack message");
+            // Receive ack
+            self.recv_msg(true).await
+        })
+        .await?;
+        let ack_msg = AckStatus::read_from_buffer_with_ctx(Endianness::BigEndian, &ack_msg_recv)
+            .map_err(|reason| PeerError::MalformedMessage {
+                kind: "acknowledgement",
+                reason,
+            })?;
         println!("Received acknowledgement message: {:?}", ack_msg);
-        if ack_msg != AckStatus::Ack {
-            return Err(PeerError::AckFailed);
+        match ack_msg {
+            AckStatus::Ack => {}
+            AckStatus::NackV1 => {
+                return Err(PeerError::Nack {
+                    motive: NackMotive::NoMotive,
+                    potential_peers: vec![],
+                })
+            }
+            AckStatus::NackV2(info) => {
+                return Err(PeerError::Nack {
+                    motive: info.motive,
+                    potential_peers: info.potential_peers_to_connect.socket_addrs(),
+                })
+            }
         }
 
+        self.info = Some(PeerInfo {
+            address: self.socket,
+            peer_id: remote_peer_id,
+            public_key: pk,
+            version,
+            metadata: remote_meta_msg,
+        });
         self.state = PeerState::Connected;
         Ok(())
     }
 
+    /// Splits the connection once the handshake is done, so one task can wait for
+    /// messages while another sends. Each half owns its side of the session keys.
+    pub fn split(self) -> Result<(PeerReader, PeerWriter), PeerError> {
+        let peer_crypto = self
+            .peer_crypto
+            .ok_or(PeerError::PeerCryptoNotInitialized)?;
+        let (crypto_writer, crypto_reader) = peer_crypto.split();
+        let (read_half, write_half) = tokio::io::split(self.stream);
+        Ok((
+            PeerReader {
+                stream: read_half,
+                peer_crypto: crypto_reader,
+                reassembler: self.reassembler,
+                chain_id: self.config.chain_id,
+                idle_timeout: self.config.timeouts.idle,
+                idle_timed_out: self.idle_timed_out,
+            },
+            PeerWriter {
+                stream: write_half,
+                peer_crypto: crypto_writer,
+                max_message_size: self.config.max_message_size,
+            },
+        ))
+    }
+
+    /// Runs the connection in background tasks once the handshake is done,
+    /// see [`PeerHandle`].
+    pub fn spawn(self) -> Result<PeerHandle, PeerError> {
+        let info = self.info.clone();
+        let (reader, writer) = self.split()?;
+        let info = info.ok_or(PeerError::ConnectionFailed)?;
+        Ok(PeerHandle::start(info, reader, writer))
+    }
+
     pub async fn send_msg(&mut self, bytes: Vec<u8>, encryption: bool) -> Result<(), PeerError> {
-        let mut stream = self.stream.lock().await;
         let data = if encryption {
             let peer_crypt_mutable = self.peer_crypto.as_mut();
             match peer_crypt_mutable {
@@ -177,26 +539,11 @@ impl Peer {
             bytes
         };
 
-        let raw = msg_bytes_to_raw(&data);
-        println!("Sending message length: {:?}", raw.len());
-        stream.write_all(&raw).await.map_err(PeerError::Io)?;
-        Ok(())
+        write_chunk(&mut self.stream, &data).await
     }
 
     pub async fn recv_msg(&mut self, encryption: bool) -> Result<Vec<u8>, PeerError> {
-        let mut buffer_len = [0u8; CONTENT_LENGTH_FIELD_BYTES];
-        let mut stream = self.stream.lock().await;
-        stream
-            .read_exact(&mut buffer_len)
-            .await
-            .map_err(PeerError::Io)?;
-
-        let mlen = u16::from_be_bytes(buffer_len);
-        let mut buffer = vec![0u8; mlen as usize];
-        stream
-            .read_exact(&mut buffer)
-            .await
-            .map_err(PeerError::Io)?;
+        let buffer = read_chunk(&mut self.stream).await?;
         if encryption && !buffer.is_empty() {
             let peer_crypt_mutable = self.peer_crypto.as_mut();
             return match peer_crypt_mutable {
@@ -206,11 +553,649 @@ impl Peer {
         }
         Ok(buffer)
     }
+
+    /// Sends an application message, split over as many encrypted chunks as needed.
+    pub async fn send_chunked(&mut self, message: &[u8]) -> Result<(), PeerError> {
+        let chunks = chunk::split_message(message, self.config.max_message_size)
+            .map_err(PeerError::Chunk)?;
+        for chunk in chunks {
+            self.send_msg(chunk, true).await?;
+        }
+        Ok(())
+    }
+
+    /// Receives encrypted chunks until a whole application message is available.
+    /// An idle timeout may leave a chunk partly read, the connection can only be
+    /// closed afterwards and later reads fail with [`PeerError::Desynchronized`].
+    pub async fn recv_chunked(&mut self) -> Result<Vec<u8>, PeerError> {
+        loop {
+            if let Some(message) = self.reassembler.next_message().map_err(PeerError::Chunk)? {
+                return Ok(message);
+            }
+            if self.idle_timed_out {
+                return Err(PeerError::Desynchronized);
+            }
+            let idle = self.config.timeouts.idle;
+            let chunk = with_optional_timeout(idle, TimeoutStage::Idle, self.recv_msg(true))
+                .await
+                .inspect_err(|e| self.idle_timed_out = is_idle_timeout(e))?;
+            self.reassembler.push(&chunk);
+        }
+    }
+
+    pub async fn send_message(&mut self, message: &PeerMessage) -> Result<(), PeerError> {
+        let bytes = message
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .map_err(PeerError::SpeedyFailed)?;
+        self.send_chunked(&bytes).await
+    }
+
+    pub async fn recv_message(&mut self) -> Result<PeerMessage, PeerError> {
+        let bytes = self.recv_chunked().await?;
+        decode_peer_message(&bytes, self.config.chain_id)
+    }
 }
 
-fn msg_bytes_to_raw(content: &[u8]) -> Vec<u8> {
+/// Receiving half of a [`Peer`] after the handshake
+pub struct PeerReader {
+    stream: TransportReader,
+    peer_crypto: PeerCryptoReader,
+    reassembler: MessageReassembler,
+    chain_id: Option<ChainId>,
+    idle_timeout: Option<Duration>,
+    idle_timed_out: bool,
+}
+
+impl PeerReader {
+    /// Receives encrypted chunks until a whole application message is available.
+    /// Same as [`Peer::recv_chunked`], reads fail for good after an idle timeout.
+    pub async fn recv_chunked(&mut self) -> Result<Vec<u8>, PeerError> {
+        loop {
+            if let Some(message) = self.reassembler.next_message().map_err(PeerError::Chunk)? {
+                return Ok(message);
+            }
+            if self.idle_timed_out {
+                return Err(PeerError::Desynchronized);
+            }
+            let chunk = with_optional_timeout(
+                self.idle_timeout,
+                TimeoutStage::Idle,
+                read_chunk(&mut self.stream),
+            )
+            .await
+            .inspect_err(|e| self.idle_timed_out = is_idle_timeout(e))?;
+            if !chunk.is_empty() {
+                let chunk = self
+                    .peer_crypto
+                    .decrypt(&chunk)
+                    .map_err(PeerError::CryptoFailed)?;
+                self.reassembler.push(&chunk);
+            }
+        }
+    }
+
+    pub async fn recv_message(&mut self) -> Result<PeerMessage, PeerError> {
+        let bytes = self.recv_chunked().await?;
+        decode_peer_message(&bytes, self.chain_id)
+    }
+}
+
+/// Sending half of a [`Peer`] after the handshake
+pub struct PeerWriter {
+    stream: TransportWriter,
+    peer_crypto: PeerCryptoWriter,
+    max_message_size: usize,
+}
+
+impl PeerWriter {
+    /// Sends an application message, split over as many encrypted chunks as needed.
+    pub async fn send_chunked(&mut self, message: &[u8]) -> Result<(), PeerError> {
+        let chunks =
+            chunk::split_message(message, self.max_message_size).map_err(PeerError::Chunk)?;
+        for chunk in chunks {
+            let chunk = self
+                .peer_crypto
+                .encrypt(&chunk)
+                .map_err(PeerError::CryptoFailed)?;
+            write_chunk(&mut self.stream, &chunk).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn send_message(&mut self, message: &PeerMessage) -> Result<(), PeerError> {
+        let bytes = message
+            .write_to_vec_with_ctx(Endianness::BigEndian)
+            .map_err(PeerError::SpeedyFailed)?;
+        self.send_chunked(&bytes).await
+    }
+
+    /// Closes our side of the connection, the reader keeps receiving until the remote closes
+    pub async fn shutdown(&mut self) -> Result<(), PeerError> {
+        self.stream.flush().await.map_err(PeerError::Io)?;
+        self.stream.shutdown().await.map_err(PeerError::Io)
+    }
+}
+
+pub(super) async fn write_chunk<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    data: &[u8],
+) -> Result<(), PeerError> {
+    let raw = msg_bytes_to_raw(data)?;
+    println!("Sending message length: {:?}", raw.len());
+    stream.write_all(&raw).await.map_err(PeerError::Io)
+}
+
+pub(super) async fn read_chunk<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Vec<u8>, PeerError> {
+    let mut buffer_len = [0u8; CONTENT_LENGTH_FIELD_BYTES];
+    stream
+        .read_exact(&mut buffer_len)
+        .await
+        .map_err(PeerError::Io)?;
+
+    let mlen = u16::from_be_bytes(buffer_len);
+    let mut buffer = vec![0u8; mlen as usize];
+    stream
+        .read_exact(&mut buffer)
+        .await
+        .map_err(PeerError::Io)?;
+    Ok(buffer)
+}
+
+/// Decodes a peer message, rejecting it when it is about another chain than `chain_id`
+pub(super) fn decode_peer_message(
+    bytes: &[u8],
+    chain_id: Option<ChainId>,
+) -> Result<PeerMessage, PeerError> {
+    let message =
+        PeerMessage::read_from_buffer_with_ctx(Endianness::BigEndian, bytes).map_err(|reason| {
+            PeerError::MalformedMessage {
+                kind: "peer",
+                reason,
+            }
+        })?;
+    if let (Some(expected), Some(found)) = (chain_id, message.chain_id()) {
+        if expected != *found {
+            return Err(PeerError::UnexpectedChainId {
+                expected,
+                found: *found,
+            });
+        }
+    }
+    Ok(message)
+}
+
+pub(super) fn msg_bytes_to_raw(content: &[u8]) -> Result<Vec<u8>, PeerError> {
+    if content.len() > MAX_CHUNK_SIZE {
+        return Err(PeerError::Chunk(ChunkError::ChunkTooLarge {
+            size: content.len(),
+        }));
+    }
     let mut bytes = Vec::with_capacity(CONTENT_LENGTH_FIELD_BYTES + content.len());
     bytes.extend_from_slice(&(content.len() as u16).to_be_bytes());
     bytes.extend(content);
-    bytes.clone()
+    Ok(bytes)
+}
+
+/// Two peers connected over an in-memory pipe that completed their handshake
+#[cfg(test)]
+pub(crate) async fn connected_peers(config: PeerConfig) -> (Peer, Peer) {
+    connected_peers_with(config.clone(), config).await
+}
+
+/// Same as [`connected_peers`], with its own config on each side
+#[cfg(test)]
+pub(crate) async fn connected_peers_with(
+    initiator_config: PeerConfig,
+    responder_config: PeerConfig,
+) -> (Peer, Peer) {
+    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 9732));
+    let initiator_identity = Identity::from_json(crate::constants::DEFAUL_IDENTITY_JSON).unwrap();
+    let responder_identity = Identity::from_json(crate::constants::REMOTE_IDENTITY_JSON).unwrap();
+    let (initiator_stream, responder_stream) = tokio::io::duplex(2 * MAX_CHUNK_SIZE);
+
+    let mut initiator = Peer::from_stream(
+        initiator_stream,
+        addr,
+        initiator_identity.clone(),
+        initiator_config,
+    );
+    let mut responder = Peer::accept_stream(
+        responder_stream,
+        addr,
+        addr.port(),
+        responder_identity.clone(),
+        responder_config,
+    );
+    let (initiated, responded) = tokio::join!(initiator.handshake(), responder.handshake());
+    initiated.unwrap();
+    responded.unwrap();
+    assert_eq!(
+        initiator.remote_peer_id(),
+        Some(&responder_identity.peer_id)
+    );
+    assert_eq!(
+        responder.remote_peer_id(),
+        Some(&initiator_identity.peer_id)
+    );
+    (initiator, responder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::{DEFAUL_IDENTITY_JSON, REMOTE_IDENTITY_JSON},
+        msgs::{ddb::GetCurrentHeadMessage, peer::AdvertiseMessage},
+    };
+    use tokio::net::TcpListener;
+
+    async fn handshake_pair(
+        initiator_config: PeerConfig,
+        responder_config: PeerConfig,
+    ) -> (
+        Result<NetworkVersion, PeerError>,
+        Result<NetworkVersion, PeerError>,
+    ) {
+        refused_handshake_pair(initiator_config, responder_config, None).await
+    }
+
+    async fn refused_handshake_pair(
+        initiator_config: PeerConfig,
+        responder_config: PeerConfig,
+        refusal: Option<NackMotive>,
+    ) -> (
+        Result<NetworkVersion, PeerError>,
+        Result<NetworkVersion, PeerError>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let identity = Identity::from_json(DEFAUL_IDENTITY_JSON).unwrap();
+
+        let responder = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let remote_identity = Identity::from_json(REMOTE_IDENTITY_JSON).unwrap();
+            let mut peer = Peer::accept(stream, remote_identity, responder_config).unwrap();
+            assert!(peer.is_incoming());
+            peer.handshake_with(refusal).await?;
+            assert_eq!(
+                peer.remote_peer_id().map(|id| id.to_string()),
+                Some("idsfYM6UbG2nhNS1dqhsJEchaDhmd9".to_string())
+            );
+            Ok(peer.version().cloned().unwrap())
+        });
+
+        let mut peer = Peer::connect(addr, identity, initiator_config)
+            .await
+            .unwrap();
+        assert!(!peer.is_incoming());
+        let initiator = peer
+            .handshake()
+            .await
+            .map(|_| peer.version().cloned().unwrap());
+        if initiator.is_ok() {
+            assert_eq!(
+                peer.remote_peer_id().map(|id| id.to_string()),
+                Some("idtCFAZinPMECvZCCcbUTX5HwSxn3C".to_string())
+            );
+        }
+        (initiator, responder.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_handshake_between_two_peers() {
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (initiator, responder) = handshake_pair(config.clone(), config).await;
+        initiator.unwrap();
+        responder.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handshake_exposes_remote_metadata() {
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (initiator, responder) =
+            connected_peers_with(config.clone(), config.with_metadata(true, true)).await;
+
+        let info = initiator.info().unwrap();
+        assert_eq!(info.address, initiator.socket());
+        assert_eq!(info.metadata, MetadataMessage::new(true, true));
+
+        let responder_info = responder.info().unwrap();
+        assert_eq!(responder_info.metadata, MetadataMessage::new(false, false));
+        assert_eq!(
+            responder_info.peer_id.to_string(),
+            "idsfYM6UbG2nhNS1dqhsJEchaDhmd9"
+        );
+        assert_eq!(info.peer_id.to_string(), "idtCFAZinPMECvZCCcbUTX5HwSxn3C");
+    }
+
+    #[tokio::test]
+    async fn test_exchange_message_larger_than_a_chunk() {
+        let (mut initiator, mut responder) =
+            connected_peers(PeerConfig::new("TEZOS_MAINNET".to_string())).await;
+        let message: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+
+        let (sent, received) =
+            tokio::join!(initiator.send_chunked(&message), responder.recv_chunked());
+        sent.unwrap();
+        let received = received.unwrap();
+        let (sent, received) =
+            tokio::join!(responder.send_chunked(&received), initiator.recv_chunked());
+        sent.unwrap();
+        assert_eq!(received.unwrap(), message);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_peer_messages() {
+        let (mut initiator, mut responder) =
+            connected_peers(PeerConfig::new("TEZOS_MAINNET".to_string())).await;
+        let advertise =
+            PeerMessage::Advertise(AdvertiseMessage::new(vec!["1.2.3.4:9732".to_string()]));
+
+        initiator
+            .send_message(&PeerMessage::Bootstrap)
+            .await
+            .unwrap();
+        assert_eq!(
+            responder.recv_message().await.unwrap(),
+            PeerMessage::Bootstrap
+        );
+        responder.send_message(&advertise).await.unwrap();
+        assert_eq!(initiator.recv_message().await.unwrap(), advertise);
+        initiator
+            .send_message(&PeerMessage::Disconnect)
+            .await
+            .unwrap();
+        assert_eq!(
+            responder.recv_message().await.unwrap(),
+            PeerMessage::Disconnect
+        );
+    }
+
+    #[tokio::test]
+    async fn test_split_peer_sends_while_receiving() {
+        let (initiator, responder) =
+            connected_peers(PeerConfig::new("TEZOS_MAINNET".to_string())).await;
+        let advertise =
+            PeerMessage::Advertise(AdvertiseMessage::new(vec!["1.2.3.4:9732".to_string()]));
+
+        let responder = tokio::spawn({
+            let advertise = advertise.clone();
+            async move {
+                let (mut reader, mut writer) = responder.split().unwrap();
+                assert_eq!(reader.recv_message().await.unwrap(), PeerMessage::Bootstrap);
+                writer.send_message(&advertise).await.unwrap();
+                writer.shutdown().await.unwrap();
+                assert!(matches!(reader.recv_message().await, Err(PeerError::Io(_))));
+            }
+        });
+
+        let (mut reader, mut writer) = initiator.split().unwrap();
+        // The reader is already waiting when the writer sends the request
+        let received = tokio::spawn(async move { reader.recv_message().await });
+        tokio::task::yield_now().await;
+        writer.send_message(&PeerMessage::Bootstrap).await.unwrap();
+        assert_eq!(received.await.unwrap().unwrap(), advertise);
+        writer.shutdown().await.unwrap();
+        responder.await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_handshake_over_unix_socket() {
+        let (initiator_stream, responder_stream) = tokio::net::UnixStream::pair().unwrap();
+        let addr = "192.0.2.1:9732".parse().unwrap();
+        let identity = Identity::from_json(DEFAUL_IDENTITY_JSON).unwrap();
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let mut initiator = Peer::from_stream(initiator_stream, addr, identity, config.clone());
+        let remote_identity = Identity::from_json(REMOTE_IDENTITY_JSON).unwrap();
+        let mut responder =
+            Peer::accept_stream(responder_stream, addr, 9732, remote_identity, config);
+        assert!(!initiator.is_incoming());
+        assert!(responder.is_incoming());
+
+        let (initiated, responded) = tokio::join!(initiator.handshake(), responder.handshake());
+        initiated.unwrap();
+        responded.unwrap();
+        assert_eq!(initiator.info().unwrap().address, addr);
+
+        initiator
+            .send_message(&PeerMessage::Bootstrap)
+            .await
+            .unwrap();
+        assert_eq!(
+            responder.recv_message().await.unwrap(),
+            PeerMessage::Bootstrap
+        );
+    }
+
+    #[tokio::test]
+    async fn test_initiator_announces_listening_port() {
+        let addr = "127.0.0.1:19733".parse().unwrap();
+        let identity = Identity::from_json(DEFAUL_IDENTITY_JSON).unwrap();
+        for (config, port) in [
+            (PeerConfig::new("TEZOS_MAINNET".to_string()), 9732),
+            (
+                PeerConfig::new("TEZOS_MAINNET".to_string()).with_listening_port(19732),
+                19732,
+            ),
+        ] {
+            let (stream, mut remote) = tokio::io::duplex(MAX_CHUNK_SIZE);
+            let mut peer = Peer::from_stream(stream, addr, identity.clone(), config);
+            let handshake = tokio::spawn(async move { peer.handshake().await });
+            let sent = read_chunk(&mut remote).await.unwrap();
+            let message =
+                ConnectionMessage::read_from_buffer_with_ctx(Endianness::BigEndian, &sent).unwrap();
+            assert_eq!(message.port, port);
+            drop(remote);
+            assert!(handshake.await.unwrap().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_split_before_handshake() {
+        let (stream, _remote) = tokio::io::duplex(MAX_CHUNK_SIZE);
+        let addr = "127.0.0.1:9732".parse().unwrap();
+        let identity = Identity::from_json(DEFAUL_IDENTITY_JSON).unwrap();
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let peer = Peer::from_stream(stream, addr, identity, config);
+        assert!(matches!(
+            peer.split(),
+            Err(PeerError::PeerCryptoNotInitialized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_times_out_on_silent_peer() {
+        // The other end of the pipe stays open but never speaks
+        let (stream, _silent) = tokio::io::duplex(MAX_CHUNK_SIZE);
+        let addr = "127.0.0.1:9732".parse().unwrap();
+        let identity = Identity::from_json(DEFAUL_IDENTITY_JSON).unwrap();
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string())
+            .with_handshake_timeout(Duration::from_millis(100));
+        let mut peer = Peer::from_stream(stream, addr, identity, config);
+        assert!(matches!(
+            peer.handshake().await,
+            Err(PeerError::Timeout {
+                stage: TimeoutStage::ConnectionMessage
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_idle_read_timeout() {
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string())
+            .with_idle_timeout(Duration::from_millis(100));
+        let (mut initiator, mut responder) = connected_peers(config).await;
+        assert!(matches!(
+            initiator.recv_message().await,
+            Err(PeerError::Timeout {
+                stage: TimeoutStage::Idle
+            })
+        ));
+        // The timed out read may have consumed part of this chunk, it is not read
+        responder
+            .send_message(&PeerMessage::Bootstrap)
+            .await
+            .unwrap();
+        assert!(matches!(
+            initiator.recv_message().await,
+            Err(PeerError::Desynchronized)
+        ));
+
+        let (mut reader, _writer) = responder.split().unwrap();
+        assert!(matches!(
+            reader.recv_message().await,
+            Err(PeerError::Timeout {
+                stage: TimeoutStage::Idle
+            })
+        ));
+        initiator
+            .send_message(&PeerMessage::Bootstrap)
+            .await
+            .unwrap();
+        assert!(matches!(
+            reader.recv_message().await,
+            Err(PeerError::Desynchronized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recv_message_for_another_chain() {
+        let mainnet: ChainId = "NetXdQprcVkpaWU".parse().unwrap();
+        let ghostnet: ChainId = "NetXnHfVqm9iesp".parse().unwrap();
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (mut initiator, mut responder) =
+            connected_peers_with(config.clone(), config.with_chain_id(mainnet)).await;
+
+        for chain_id in [mainnet, ghostnet] {
+            let message = PeerMessage::GetCurrentHead(GetCurrentHeadMessage { chain_id });
+            initiator.send_message(&message).await.unwrap();
+        }
+        assert!(responder.recv_message().await.is_ok());
+        assert!(matches!(
+            responder.recv_message().await,
+            Err(PeerError::UnexpectedChainId { expected, found })
+                if expected == mainnet && found == ghostnet
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recv_message_above_max_size() {
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (mut initiator, mut responder) =
+            connected_peers_with(config.clone(), config.with_max_message_size(1024)).await;
+
+        initiator.send_chunked(&[0u8; 2048]).await.unwrap();
+        assert!(matches!(
+            responder.recv_chunked().await,
+            Err(PeerError::Chunk(ChunkError::MessageTooLarge {
+                size: 2048,
+                max: 1024
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_negotiates_common_version() {
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (initiator, responder) =
+            handshake_pair(config.clone(), config.with_p2p_versions(0..=0)).await;
+        assert_eq!(
+            initiator.unwrap(),
+            NetworkVersion::new("TEZOS_MAINNET".to_string(), 2, 0)
+        );
+        assert_eq!(
+            responder.unwrap(),
+            NetworkVersion::new("TEZOS_MAINNET".to_string(), 2, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handshake_fails_without_common_version() {
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (initiator, responder) =
+            handshake_pair(config.clone(), config.with_distributed_db_versions(3..=4)).await;
+        assert!(matches!(responder, Err(PeerError::NoCommonVersion { .. })));
+        assert!(matches!(
+            initiator,
+            Err(PeerError::Nack {
+                motive: NackMotive::DeprecatedDistributedDbVersion,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_fails_with_unknown_chain_name() {
+        let (initiator, responder) = handshake_pair(
+            PeerConfig::new("TEZOS_GHOSTNET".to_string()),
+            PeerConfig::new("TEZOS_MAINNET".to_string()),
+        )
+        .await;
+        assert!(matches!(initiator, Err(PeerError::NoCommonVersion { .. })));
+        assert!(matches!(responder, Err(PeerError::NoCommonVersion { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_refused_with_known_points() {
+        let known_point: std::net::SocketAddr = "10.0.0.1:9732".parse().unwrap();
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (initiator, responder) = refused_handshake_pair(
+            config.clone(),
+            config.with_known_points(vec![known_point]),
+            Some(NackMotive::TooManyConnections),
+        )
+        .await;
+        assert!(matches!(
+            responder,
+            Err(PeerError::Refused {
+                motive: NackMotive::TooManyConnections
+            })
+        ));
+        match initiator {
+            Err(PeerError::Nack {
+                motive,
+                potential_peers,
+            }) => {
+                assert_eq!(motive, NackMotive::TooManyConnections);
+                assert_eq!(potential_peers, vec![known_point]);
+            }
+            other => panic!("Unexpected handshake result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_nack_v1_for_first_p2p_version() {
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (initiator, _) = handshake_pair(
+            config.clone().with_p2p_versions(0..=0),
+            config.with_distributed_db_versions(3..=4),
+        )
+        .await;
+        assert!(matches!(
+            initiator,
+            Err(PeerError::Nack {
+                motive: NackMotive::NoMotive,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_insufficient_pow() {
+        let config = PeerConfig::new("TEZOS_MAINNET".to_string());
+        let (initiator, responder) =
+            handshake_pair(config.clone(), config.with_expected_pow(64.0)).await;
+        assert!(matches!(
+            responder,
+            Err(PeerError::InsufficientProofOfWork { .. })
+        ));
+        assert!(matches!(
+            initiator,
+            Err(PeerError::Nack {
+                motive: NackMotive::NoMotive,
+                ..
+            })
+        ));
+    }
 }
@@ -0,0 +1,11 @@
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+
+/// Byte stream a [`Peer`](super::peer::Peer) can run over: a TCP or Unix socket,
+/// a proxied connection or an in-memory `tokio::io::duplex` pipe.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+pub type BoxedTransport = Box<dyn Transport>;
+pub type TransportReader = ReadHalf<BoxedTransport>;
+pub type TransportWriter = WriteHalf<BoxedTransport>;
@@ -0,0 +1,185 @@
+use crate::msgs::{ack::NackMotive, connection::NetworkVersion};
+use std::{fmt, ops::RangeInclusive};
+
+pub const DEFAULT_DISTRIBUTED_DB_VERSIONS: RangeInclusive<u16> = 0..=2;
+pub const DEFAULT_P2P_VERSIONS: RangeInclusive<u16> = 0..=1;
+
+/// Versions we are able to speak for a chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SupportedVersions {
+    pub chain_name: String,
+    pub distributed_db_versions: RangeInclusive<u16>,
+    pub p2p_versions: RangeInclusive<u16>,
+}
+
+impl SupportedVersions {
+    pub fn new(chain_name: String) -> Self {
+        Self {
+            chain_name,
+            distributed_db_versions: DEFAULT_DISTRIBUTED_DB_VERSIONS,
+            p2p_versions: DEFAULT_P2P_VERSIONS,
+        }
+    }
+
+    /// Versions announced in our connection message, our best one.
+    // octez nodes read a single version, so announcing more would be refused
+    pub fn announced(&self) -> Vec<NetworkVersion> {
+        vec![NetworkVersion::new(
+            self.chain_name.clone(),
+            *self.distributed_db_versions.end(),
+            *self.p2p_versions.end(),
+        )]
+    }
+
+    /// Picks the best version both sides support among the `remote` ones,
+    /// each remote version is lowered to our best one when it is newer.
+    pub fn select(&self, remote: &[NetworkVersion]) -> Option<NetworkVersion> {
+        remote
+            .iter()
+            .filter(|v| v.chain_name == self.chain_name)
+            .filter_map(|v| {
+                let distributed_db_version = v
+                    .distributed_db_version
+                    .min(*self.distributed_db_versions.end());
+                let p2p_version = v.p2p_version.min(*self.p2p_versions.end());
+                let supported = self
+                    .distributed_db_versions
+                    .contains(&distributed_db_version)
+                    && self.p2p_versions.contains(&p2p_version);
+                supported.then_some((p2p_version, distributed_db_version))
+            })
+            .max()
+            .map(|(p2p_version, distributed_db_version)| {
+                NetworkVersion::new(self.chain_name.clone(), distributed_db_version, p2p_version)
+            })
+    }
+
+    /// Same as [`SupportedVersions::select`], telling which Nack motive
+    /// matches the remote versions when none of them is acceptable.
+    pub fn check(&self, remote: &[NetworkVersion]) -> Result<NetworkVersion, NackMotive> {
+        if let Some(version) = self.select(remote) {
+            return Ok(version);
+        }
+
+        let mut same_chain = remote
+            .iter()
+            .filter(|v| v.chain_name == self.chain_name)
+            .peekable();
+        if same_chain.peek().is_none() {
+            return Err(NackMotive::UnknownChainName);
+        }
+        let p2p_supported = same_chain.any(|v| {
+            self.p2p_versions
+                .contains(&v.p2p_version.min(*self.p2p_versions.end()))
+        });
+        if p2p_supported {
+            Err(NackMotive::DeprecatedDistributedDbVersion)
+        } else {
+            Err(NackMotive::DeprecatedP2pVersion)
+        }
+    }
+}
+
+impl fmt::Display for SupportedVersions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (distributed_db {}..={}, p2p {}..={})",
+            self.chain_name,
+            self.distributed_db_versions.start(),
+            self.distributed_db_versions.end(),
+            self.p2p_versions.start(),
+            self.p2p_versions.end()
+        )
+    }
+}
+
+/// Formats a list of versions for error messages
+pub fn display_versions(versions: &[NetworkVersion]) -> String {
+    if versions.is_empty() {
+        return "none".to_string();
+    }
+    versions
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mainnet() -> SupportedVersions {
+        SupportedVersions::new("TEZOS_MAINNET".to_string())
+    }
+
+    fn version(chain_name: &str, distributed_db_version: u16, p2p_version: u16) -> NetworkVersion {
+        NetworkVersion::new(chain_name.to_string(), distributed_db_version, p2p_version)
+    }
+
+    #[test]
+    fn test_announced_is_best_version() {
+        assert_eq!(mainnet().announced(), vec![version("TEZOS_MAINNET", 2, 1)]);
+    }
+
+    #[test]
+    fn test_select_same_version() {
+        let selected = mainnet().select(&[version("TEZOS_MAINNET", 2, 1)]);
+        assert_eq!(selected, Some(version("TEZOS_MAINNET", 2, 1)));
+    }
+
+    #[test]
+    fn test_select_lowers_newer_remote_version() {
+        let selected = mainnet().select(&[version("TEZOS_MAINNET", 5, 3)]);
+        assert_eq!(selected, Some(version("TEZOS_MAINNET", 2, 1)));
+    }
+
+    #[test]
+    fn test_select_best_common_version() {
+        let selected = mainnet().select(&[
+            version("TEZOS_MAINNET", 1, 0),
+            version("TEZOS_MAINNET", 2, 1),
+            version("TEZOS_MAINNET", 0, 1),
+        ]);
+        assert_eq!(selected, Some(version("TEZOS_MAINNET", 2, 1)));
+    }
+
+    #[test]
+    fn test_select_no_common_version() {
+        let local = SupportedVersions {
+            distributed_db_versions: 1..=2,
+            p2p_versions: 1..=1,
+            ..mainnet()
+        };
+        assert_eq!(local.select(&[version("TEZOS_MAINNET", 0, 1)]), None);
+        assert_eq!(local.select(&[version("TEZOS_MAINNET", 2, 0)]), None);
+        assert_eq!(local.select(&[version("TEZOS_GHOSTNET", 2, 1)]), None);
+        assert_eq!(local.select(&[]), None);
+    }
+
+    #[test]
+    fn test_check_nack_motives() {
+        let local = SupportedVersions {
+            distributed_db_versions: 1..=2,
+            p2p_versions: 1..=1,
+            ..mainnet()
+        };
+        assert_eq!(
+            local.check(&[version("TEZOS_MAINNET", 2, 1)]),
+            Ok(version("TEZOS_MAINNET", 2, 1))
+        );
+        assert_eq!(
+            local.check(&[version("TEZOS_GHOSTNET", 2, 1)]),
+            Err(NackMotive::UnknownChainName)
+        );
+        assert_eq!(
+            local.check(&[version("TEZOS_MAINNET", 2, 0)]),
+            Err(NackMotive::DeprecatedP2pVersion)
+        );
+        assert_eq!(
+            local.check(&[version("TEZOS_MAINNET", 0, 1)]),
+            Err(NackMotive::DeprecatedDistributedDbVersion)
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use crate::crypto::key::wipe;
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    process::{Command, Stdio},
+};
+
+/// Environment variable holding the identity passphrase, for unattended runs
+pub const PASSPHRASE_ENV: &str = "TEZOS_IDENTITY_PASSPHRASE";
+/// Environment variable naming a file descriptor to read the passphrase from,
+/// e.g. `TEZOS_IDENTITY_PASSPHRASE_FD=3 ... 3< passphrase.txt`
+pub const PASSPHRASE_FD_ENV: &str = "TEZOS_IDENTITY_PASSPHRASE_FD";
+
+const TTY: &str = "/dev/tty";
+
+/// Reads the identity passphrase from [`PASSPHRASE_ENV`], then from the file descriptor
+/// in [`PASSPHRASE_FD_ENV`], and otherwise asks for it on the terminal.
+pub fn read_passphrase(prompt: &str) -> io::Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    if let Ok(fd) = env::var(PASSPHRASE_FD_ENV) {
+        return read_passphrase_fd(&fd);
+    }
+    prompt_passphrase(prompt)
+}
+
+/// Same as [`read_passphrase`], a passphrase typed on the terminal is asked twice
+/// so a typo does not lock the identity away. An empty passphrase is refused.
+pub fn read_new_passphrase(prompt: &str) -> io::Result<String> {
+    if env::var_os(PASSPHRASE_ENV).is_some() || env::var_os(PASSPHRASE_FD_ENV).is_some() {
+        return non_empty(read_passphrase(prompt)?);
+    }
+    let passphrase = non_empty(prompt_passphrase(prompt)?)?;
+    let confirmation = prompt_passphrase("Confirm passphrase: ")?;
+    let matches = confirmation == passphrase;
+    wipe(confirmation);
+    if !matches {
+        wipe(passphrase);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Passphrases do not match",
+        ));
+    }
+    Ok(passphrase)
+}
+
+fn non_empty(passphrase: String) -> io::Result<String> {
+    if passphrase.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Passphrase must not be empty",
+        ));
+    }
+    Ok(passphrase)
+}
+
+#[cfg(not(unix))]
+fn read_passphrase_fd(_fd: &str) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is only supported on Unix", PASSPHRASE_FD_ENV),
+    ))
+}
+
+#[cfg(unix)]
+fn read_passphrase_fd(fd: &str) -> io::Result<String> {
+    let fd: u32 = fd.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid {} '{}'", PASSPHRASE_FD_ENV, fd),
+        )
+    })?;
+    let file = File::open(format!("/dev/fd/{}", fd))?;
+    read_line(BufReader::new(file))
+}
+
+/// Prompts on the controlling terminal with echo turned off while typing. Fails
+/// rather than showing the passphrase when the echo cannot be turned off.
+fn prompt_passphrase(prompt: &str) -> io::Result<String> {
+    let mut tty = OpenOptions::new().read(true).write(true).open(TTY)?;
+    if !set_echo(&tty, false) {
+        return Err(io::Error::other(format!(
+            "Cannot turn off the terminal echo, set {} or {} instead",
+            PASSPHRASE_ENV, PASSPHRASE_FD_ENV
+        )));
+    }
+    tty.write_all(prompt.as_bytes())?;
+    tty.flush()?;
+
+    let passphrase = read_line(BufReader::new(&tty));
+    set_echo(&tty, true);
+    tty.write_all(b"\n")?;
+    passphrase
+}
+
+/// Switches the terminal echo with `stty`, returns whether it succeeded
+fn set_echo(tty: &File, echo: bool) -> bool {
+    let Ok(stdin) = tty.try_clone() else {
+        return false;
+    };
+    Command::new("stty")
+        .arg(if echo { "echo" } else { "-echo" })
+        .stdin(Stdio::from(stdin))
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// The first line, without its line ending
+fn read_line<R: BufRead>(mut reader: R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(len);
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_line() {
+        assert_eq!(read_line(&b"secret\r\nnext\n"[..]).unwrap(), "secret");
+        assert_eq!(read_line(&b"secret"[..]).unwrap(), "secret");
+        assert_eq!(read_line(&b""[..]).unwrap(), "");
+    }
+
+    #[test]
+    fn test_non_empty() {
+        assert_eq!(non_empty("secret".to_string()).unwrap(), "secret");
+        assert_eq!(
+            non_empty(String::new()).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_passphrase_fd_invalid() {
+        assert_eq!(
+            read_passphrase_fd("three").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}